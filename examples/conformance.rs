@@ -0,0 +1,14 @@
+use sockets::websockets::conformance::{run, run_all, Target};
+
+/// `cargo run --features conformance --example conformance [server|client]`
+fn main() {
+    let report = match std::env::args().nth(1).as_deref() {
+        Some("server") => run(Target::Server),
+        Some("client") => run(Target::Client),
+        _ => run_all(),
+    };
+    println!("{report}");
+    if !report.failed().is_empty() {
+        std::process::exit(1);
+    }
+}
@@ -1,59 +1,26 @@
-use std::net::TcpListener;
-use std::net::TcpStream;
-
-use sockets::socketio::server::Server;
-use sockets::worker::ThreadPool;
+use sockets::socketio::server::{Config, Server};
 
+/// `cargo run --example sio_server [unix:<path>]`
 fn main() -> std::io::Result<()> {
-    let config = Config {
+    let mut config = Config {
         url: String::from("127.0.0.1:8001"),
         threads: 5,
         max_payload_size: 1024,
+        ..Config::default()
     };
 
-    let listener = TcpListener::bind("127.0.0.1:8001").unwrap();
-    let poll = ThreadPool::build(16);
-
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-
-        poll.excute(|| handle_connection(stream).unwrap());
-    }
-    println!("Shutting down main thread on server");
-
-    Ok(())
-}
-
-fn handle_connection(mut stream: TcpStream) -> std::io::Result<()> {
-    let mut srv = srv.handshake().unwrap();
-
-    loop {
-        let packet = srv.receive();
-        if packet.header.opcode == Opcode::Data(Data::Text) {
-            let msg = String::from_utf8(packet.payload.clone()).unwrap();
-            if msg == "ping" || msg == "Ping" {
-                srv.send_msg(String::from("Pong"));
-            } else {
-                srv.send_msg(msg)
-            }
-        }
+    match std::env::args().nth(1) {
+        #[cfg(unix)]
+        Some(arg) if arg.starts_with("unix:") => {
+            use sockets::socketio::server::SocketIoServer;
+            use std::os::unix::net::UnixStream;
 
-        if packet.header.opcode == Opcode::Control(Control::Ping) {
-            srv.send_pong();
-        }
-
-        if packet.header.opcode == Opcode::Control(Control::Close) {
-            let buf: [u8; 2] = packet.payload.try_into().unwrap();
-            let code = u16::from_be_bytes(buf);
-            println!(
-                "
-Got close call from client
->> Code: {}",
-                code
-            );
-            break;
+            config.url = String::from(&arg["unix:".len()..]);
+            Server::<UnixStream>::new(config).listen()?;
         }
+        _ => Server::create(config).listen()?,
     }
+    println!("Shutting down main thread on server");
 
     Ok(())
 }
@@ -1,25 +1,44 @@
-use std::io::BufRead;
-use std::io::BufReader;
-use std::net::TcpListener;
+use std::io::{Read, Write};
 use std::net::TcpStream;
 
 use sockets::websockets::client::Client;
+use sockets::websockets::message::{Message, CLOSE_NORMAL};
 
+/// `cargo run --example ws_client [unix:<path>]`
 fn main() {
-    let mut stream = TcpStream::connect("127.0.0.1:8001").unwrap();
-    handle_connection(stream);
+    match std::env::args().nth(1) {
+        #[cfg(unix)]
+        Some(arg) if arg.starts_with("unix:") => {
+            let path = &arg["unix:".len()..];
+            let stream = std::os::unix::net::UnixStream::connect(path).unwrap();
+            handle_connection(stream);
+        }
+        _ => {
+            let stream = TcpStream::connect("127.0.0.1:8001").unwrap();
+            handle_connection(stream);
+        }
+    }
 }
 
-fn handle_connection(mut stream: TcpStream) {
-    let ws = Client::new(&mut stream, None);
-    let _ = ws.connect();
-    let read = BufReader::new(&mut stream);
-    for lines in read.lines() {
-        match lines {
-            Ok(line) => {
-                println!("{}", line);
+fn handle_connection<S: Read + Write + Unpin>(stream: S) {
+    let mut ws = Client::new(stream, None).connect().unwrap();
+    ws.send_message(Message::Text(String::from("Ping")))
+        .unwrap();
+    loop {
+        match ws.read_message() {
+            Ok(Message::Text(text)) => {
+                println!("{}", text);
+                ws.send_close(CLOSE_NORMAL, "bye").unwrap();
+            }
+            Ok(Message::Close(close)) => {
+                println!("closed {:?}", close);
+                break;
+            }
+            Ok(_) => {}
+            Err(err) => {
+                println!("ERR>> {}", err);
+                break;
             }
-            Err(_) => {}
         }
     }
 }
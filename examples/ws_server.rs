@@ -1,57 +1,55 @@
-use std::net::TcpListener;
-use std::net::TcpStream;
-
-use sockets::websockets::frame::Control;
-use sockets::websockets::frame::Data;
-use sockets::websockets::frame::Opcode;
-use sockets::websockets::server::WebsocketConnection;
-use sockets::worker::ThreadPool;
+use sockets::websockets::hub::Config;
+use sockets::websockets::message::{Message, CLOSE_UNSUPPORTED};
+use sockets::websockets::Server;
 
+/// chat : every text message is broadcast to every connection
+///
+/// `cargo run --example ws_server [unix:<path>]`
 fn main() -> std::io::Result<()> {
-    let listener = TcpListener::bind("127.0.0.1:8001").unwrap();
-    let poll = ThreadPool::build(16);
-
-    for stream in listener.incoming() {
-        let stream = stream.unwrap();
-
-        poll.excute(|| handle_connection(stream).unwrap());
+    match std::env::args().nth(1) {
+        #[cfg(unix)]
+        Some(arg) if arg.starts_with("unix:") => {
+            let url = String::from(&arg["unix:".len()..]);
+            let server: Server<std::os::unix::net::UnixStream> = create(url);
+            server.listen()?
+        }
+        _ => {
+            let server: Server<std::net::TcpStream> = create(String::from("127.0.0.1:8001"));
+            server.listen()?
+        }
     }
     println!("Shutting down main thread on server");
 
     Ok(())
 }
 
-fn handle_connection(mut stream: TcpStream) -> std::io::Result<()> {
-    let mut srv = WebsocketConnection::new(stream, None);
-    srv.handshake().unwrap();
-
-    loop {
-        let packet = srv.receive();
-        if packet.header.opcode == Opcode::Data(Data::Text) {
-            let msg = String::from_utf8(packet.payload.clone()).unwrap();
-            if msg == "ping" || msg == "Ping" {
-                srv.send_msg(String::from("Pong"));
-            } else {
-                srv.send_msg(msg)
-            }
+fn create<S>(url: String) -> Server<S>
+where
+    S: std::io::Read + std::io::Write + Unpin,
+{
+    let mut server = Server::new(Config {
+        url,
+        ..Config::default()
+    });
+    server.on_connect(|server, id| {
+        let joined = Message::Text(format!("{id} joined"));
+        server.broadcast(joined);
+    });
+    server.on_message(|server, id, message| match message {
+        Message::Text(msg) if msg == "ping" || msg == "Ping" => {
+            let _ = server.send_to(id, Message::Text(String::from("Pong")));
         }
-
-        if packet.header.opcode == Opcode::Control(Control::Ping) {
-            srv.send_pong();
+        Message::Text(msg) => {
+            server.broadcast(Message::Text(format!("{id}: {msg}")));
         }
-
-        if packet.header.opcode == Opcode::Control(Control::Close) {
-            let buf: [u8; 2] = packet.payload.try_into().unwrap();
-            let code = u16::from_be_bytes(buf);
-            println!(
-                "
-Got close call from client
->> Code: {}",
-                code
-            );
-            break;
+        Message::Binary(_) => {
+            let _ = server.close(id, CLOSE_UNSUPPORTED, "text only");
         }
-    }
-
-    Ok(())
+        _ => {}
+    });
+    server.on_close(|server, id, close| {
+        println!("\nGot close call from client\n>> {id}: {close:?}");
+        server.broadcast(Message::Text(format!("{id} left")));
+    });
+    server
 }
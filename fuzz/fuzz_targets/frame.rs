@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sockets::websockets::frame::Frame;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(frame) = Frame::parse(data) else {
+        return;
+    };
+    let _ = frame.to_string();
+    let mut raw = Vec::new();
+    frame.format(&mut raw).unwrap();
+    assert_eq!(Frame::parse(&raw).unwrap(), frame);
+});
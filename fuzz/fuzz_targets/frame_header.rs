@@ -0,0 +1,17 @@
+#![no_main]
+
+use std::io::Cursor;
+
+use libfuzzer_sys::fuzz_target;
+use sockets::websockets::frame::FrameHeader;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(Some(header)) = FrameHeader::parse(&mut Cursor::new(data)) else {
+        return;
+    };
+    // a parsed header formats to bytes parsing to the same header
+    let mut raw = Vec::new();
+    header.format(&mut raw).unwrap();
+    let parsed = FrameHeader::parse(&mut Cursor::new(&raw)).unwrap().unwrap();
+    assert_eq!(parsed, header);
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use std::{io::Write, net::Shutdown};
+
+use libfuzzer_sys::fuzz_target;
+use sockets::{
+    net::{pipe::duplex, NetStream},
+    websockets::server::WebsocketConnection,
+};
+
+// upgrade request followed by frames, as sent by a client
+fuzz_target!(|data: &[u8]| {
+    let (server, mut client) = duplex();
+    client.write_all(data).unwrap();
+    client.shutdown(Shutdown::Write).unwrap();
+
+    let mut wc = WebsocketConnection::new(server, Some(64 * 1024));
+    if wc.handshake().is_err() {
+        return;
+    }
+    while wc.read_message().is_ok() {}
+});
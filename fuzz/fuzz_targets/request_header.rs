@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use sockets::http::header::{RequestHeader, ResponseHeader};
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(str) = std::str::from_utf8(data) {
+        let _ = RequestHeader::from(str).format();
+        let res = ResponseHeader::from(str);
+        let _ = (res.status(), res.format());
+    }
+
+    let Ok(Some((req, end))) = RequestHeader::parse(data) else {
+        return;
+    };
+    assert!(end <= data.len());
+    let _ = (req.path(), req.query("sid"));
+    // a valid head formats to a head parsing to the same header
+    let formatted = req.format();
+    let (parsed, len) = RequestHeader::parse(formatted.as_bytes()).unwrap().unwrap();
+    assert_eq!((parsed, len), (req, formatted.len()));
+});
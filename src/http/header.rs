@@ -1,11 +1,151 @@
-use std::{collections::HashMap, fmt::Display, format};
+use std::{format, io};
+
+use super::status::StatusCode;
+
+/// longest accepted request line
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// longest accepted head (request line, header fields and blank line)
+pub const MAX_HEADER_SIZE: usize = 64 * 1024;
+/// most header fields accepted in a request
+pub const MAX_HEADERS: usize = 100;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// `tchar` of RFC 9110 (method and field names)
+fn is_token(str: &str) -> bool {
+    !str.is_empty()
+        && str
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+/// lines of `head` without their line terminator (CRLF, or bare LF)
+fn lines(head: &str) -> impl Iterator<Item = &str> {
+    head.split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line))
+}
+
+/// length of the head at the start of `buf` : up to and including the
+/// empty line ending the header section (empty lines before the request
+/// line are skipped)
+pub fn find_head_end(buf: &[u8]) -> Option<usize> {
+    let mut start = 0;
+    let mut started = false;
+    for (idx, byte) in buf.iter().enumerate() {
+        if *byte != b'\n' {
+            continue;
+        }
+        let line = &buf[start..idx];
+        let empty = line.is_empty() || line == b"\r";
+        if empty && started {
+            return Some(idx + 1);
+        }
+        started |= !empty;
+        start = idx + 1;
+    }
+    None
+}
+
+/// `name: value` header field, value without surrounding whitespace
+fn parse_field(line: &str) -> io::Result<(&str, &str)> {
+    if line.starts_with([' ', '\t']) {
+        return Err(invalid("obsolete line folding is not supported"));
+    }
+    let (name, value) = line
+        .split_once(':')
+        .ok_or(invalid("header field without colon"))?;
+    if !is_token(name) {
+        return Err(invalid("invalid header field name"));
+    }
+    let value = value.trim_matches([' ', '\t']);
+    if value.chars().any(|c| c.is_ascii_control() && c != '\t') {
+        return Err(invalid("invalid header field value"));
+    }
+    Ok((name, value))
+}
+
+/// header fields in insertion order, names keep their casing and are
+/// compared case-insensitively, a name may have several values
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    fields: Vec<(String, String)>,
+}
+
+impl HeaderMap {
+    pub fn new() -> Self {
+        HeaderMap { fields: Vec::new() }
+    }
+    /// first value of field `key`
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.fields
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v)
+    }
+    /// every value of field `key`, in insertion order
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.fields
+            .iter()
+            .filter(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+    /// replace every value of field `key`, the field keeps the position
+    /// of its first occurrence (appended when new)
+    pub fn set(&mut self, key: &str, val: &str) {
+        match self
+            .fields
+            .iter()
+            .position(|(k, _)| k.eq_ignore_ascii_case(key))
+        {
+            Some(idx) => {
+                self.fields[idx] = (String::from(key), String::from(val));
+                let rest = self.fields.split_off(idx + 1);
+                self.fields.extend(
+                    rest.into_iter()
+                        .filter(|(k, _)| !k.eq_ignore_ascii_case(key)),
+                );
+            }
+            None => self.append(key, val),
+        }
+    }
+    /// add a value to field `key`, keeping the existing ones
+    pub fn append(&mut self, key: &str, val: &str) {
+        self.fields.push((String::from(key), String::from(val)));
+    }
+    /// remove every value of field `key`
+    pub fn remove(&mut self, key: &str) {
+        self.fields.retain(|(k, _)| !k.eq_ignore_ascii_case(key));
+    }
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+    /// number of fields (a name with several values counts several times)
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+    /// `(name, value)` pairs in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+    /// `name: value\r\n` lines in insertion order
+    pub fn format(&self) -> String {
+        self.iter().map(|(k, v)| format!("{k}: {v}\r\n")).collect()
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct RequestHeader {
     method: String,
     route: String,
     protocol: String,
-    data: HashMap<String, String>,
+    /// fields in received order
+    headers: HeaderMap,
 }
 
 impl RequestHeader {
@@ -14,44 +154,125 @@ impl RequestHeader {
             method: String::from("GET"),
             route: String::from("/"),
             protocol: String::from("HTTP/1.1"),
-            data: HashMap::new(),
+            headers: HeaderMap::new(),
         }
     }
+    /// lenient parsing : empty lines are skipped, invalid lines ignored
     pub fn from(str: &str) -> Self {
         Self::__from_internal(str)
     }
+    /// parse request head at the start of `buf`
+    ///
+    /// returns the header and the length of the head (blank line included),
+    /// or `None` while the head is incomplete
+    pub fn parse(buf: &[u8]) -> io::Result<Option<(Self, usize)>> {
+        let Some(end) = find_head_end(buf) else {
+            let first_line = buf.iter().position(|b| *b == b'\n').unwrap_or(buf.len());
+            if first_line > MAX_REQUEST_LINE {
+                return Err(invalid("request line too long"));
+            }
+            if buf.len() > MAX_HEADER_SIZE {
+                return Err(invalid("header section too large"));
+            }
+            return Ok(None);
+        };
+        if end > MAX_HEADER_SIZE {
+            return Err(invalid("header section too large"));
+        }
+        let head = std::str::from_utf8(&buf[..end]).map_err(|_| invalid("request is not utf-8"))?;
+
+        let mut lines = lines(head).skip_while(|line| line.is_empty());
+        let request_line = lines.next().ok_or(invalid("missing request line"))?;
+        if request_line.len() > MAX_REQUEST_LINE {
+            return Err(invalid("request line too long"));
+        }
+
+        let mut hdr = Self::new();
+        let mut words = request_line.split(' ');
+        let (Some(method), Some(route), Some(protocol), None) =
+            (words.next(), words.next(), words.next(), words.next())
+        else {
+            return Err(invalid("malformed request line"));
+        };
+        if !is_token(method) || route.is_empty() || !protocol.starts_with("HTTP/") {
+            return Err(invalid("malformed request line"));
+        }
+        hdr.method = String::from(method);
+        hdr.route = String::from(route);
+        hdr.protocol = String::from(protocol);
+
+        for line in lines.take_while(|line| !line.is_empty()) {
+            if hdr.headers.len() == MAX_HEADERS {
+                return Err(invalid("too many header fields"));
+            }
+            let (name, value) = parse_field(line)?;
+            hdr.headers.append(name, value);
+        }
+
+        Ok(Some((hdr, end)))
+    }
+    /// first value of field `key` (case-insensitive)
     pub fn get(&self, key: &str) -> Option<&String> {
-        self.data.get(key)
+        self.headers.get(key)
+    }
+    /// every value of field `key` (case-insensitive), in received order
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.headers.get_all(key)
     }
+    /// replace every value of field `key`
     pub fn set(&mut self, key: &str, val: &str) {
-        self.data.insert(String::from(key), String::from(val));
+        self.headers.set(key, val);
+    }
+    /// add a value to field `key`
+    pub fn append(&mut self, key: &str, val: &str) {
+        self.headers.append(key, val);
+    }
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+    /// request target including query string
+    pub fn route(&self) -> &str {
+        &self.route
+    }
+    /// request target without query string
+    pub fn path(&self) -> &str {
+        match self.route.split_once('?') {
+            Some((path, _)) => path,
+            None => &self.route,
+        }
+    }
+    /// value of query string parameter
+    pub fn query(&self, key: &str) -> Option<&str> {
+        let (_, query) = self.route.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+            .find(|(k, _)| *k == key)
+            .map(|(_, v)| v)
     }
 
     fn __from_internal(str: &str) -> Self {
         let mut hdr = Self::new();
 
-        let mut offset = 0;
-        for (lidx, line) in str.lines().enumerate() {
-            if line.is_empty() {
-                offset += 1;
-                continue;
-            }
-            if lidx == offset {
-                // first line
-                for (widx, words) in line.split(" ").enumerate() {
-                    if widx == 0 {
-                        hdr.method = String::from(words);
-                    } else if widx == 1 {
-                        hdr.route = String::from(words);
-                    } else if widx == 2 {
-                        hdr.protocol = String::from(words);
-                    }
+        let mut lines = lines(str).filter(|line| !line.is_empty());
+        if let Some(line) = lines.next() {
+            // first line
+            for (widx, words) in line.split(" ").enumerate() {
+                if widx == 0 {
+                    hdr.method = String::from(words);
+                } else if widx == 1 {
+                    hdr.route = String::from(words);
+                } else if widx == 2 {
+                    hdr.protocol = String::from(words);
                 }
-            } else {
-                let mut words = line.split(": ");
-                let key = words.next().unwrap();
-                let val = words.next().unwrap();
-                hdr.set(key, val);
+            }
+        }
+        for line in lines {
+            if let Ok((key, val)) = parse_field(line) {
+                hdr.headers.append(key, val);
             }
         }
         hdr
@@ -59,21 +280,16 @@ impl RequestHeader {
 
     pub fn format(&self) -> String {
         let first_line = format!("{} {} {}\r\n", self.method, self.route, self.protocol);
-        let lines: String = self
-            .data
-            .iter()
-            .map(|(k, v)| format!("{k}: {v}\r\n"))
-            .collect();
 
-        first_line + &lines
+        first_line + &self.headers.format() + "\r\n"
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ResponseHeader {
     protocol: String,
     status: String,
-    data: HashMap<String, String>,
+    headers: HeaderMap,
 }
 impl Default for ResponseHeader {
     fn default() -> Self {
@@ -86,17 +302,41 @@ impl ResponseHeader {
         ResponseHeader {
             protocol: String::from("HTTP/1.1"),
             status: String::from("101 Switching Protocols"),
-            data: HashMap::new(),
+            headers: HeaderMap::new(),
         }
     }
     pub fn from(str: &str) -> Self {
         Self::__from_internal(str)
     }
+    /// first value of field `key` (case-insensitive)
     pub fn get(&self, key: &str) -> Option<&String> {
-        self.data.get(key)
+        self.headers.get(key)
     }
+    /// replace every value of field `key`
     pub fn set(&mut self, key: &str, val: &str) {
-        self.data.insert(String::from(key), String::from(val));
+        self.headers.set(key, val);
+    }
+    /// add a value to field `key` (e.g. several `Set-Cookie`)
+    pub fn append(&mut self, key: &str, val: &str) {
+        self.headers.append(key, val);
+    }
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+    /// response head with `status` and no field
+    pub fn with_status(status: StatusCode) -> Self {
+        let mut hdr = Self::new();
+        hdr.set_status(status);
+        hdr
+    }
+    /// status code, `None` when the status line is malformed
+    pub fn status(&self) -> Option<StatusCode> {
+        let (code, _) = self.status.split_once(' ').unwrap_or((&self.status, ""));
+        StatusCode::from_u16(code.parse().ok()?)
+    }
+    /// set status line with the standard reason phrase
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status = status.to_string();
     }
 
     fn __from_internal(str: &str) -> Self {
@@ -110,14 +350,13 @@ impl ResponseHeader {
             }
             if lidx == offset {
                 // first line
-                let (protocol, status) = line.split_once(" ").unwrap();
+                let (protocol, status) = line.split_once(' ').unwrap_or((line, ""));
                 hdr.protocol = String::from(protocol);
                 hdr.status = String::from(status);
             } else {
-                let mut words = line.split(": ");
-                let key = words.next().unwrap();
-                let val = words.next().unwrap();
-                hdr.set(key, val);
+                if let Ok((key, val)) = parse_field(line) {
+                    hdr.append(key, val);
+                }
             }
         }
         hdr
@@ -125,13 +364,8 @@ impl ResponseHeader {
 
     pub fn format(&self) -> String {
         let first_line = format!("{} {}\r\n", self.protocol, self.status);
-        let lines: String = self
-            .data
-            .iter()
-            .map(|(k, v)| format!("{k}: {v}\r\n"))
-            .collect();
 
-        first_line + &lines + "\r\n"
+        first_line + &self.headers.format() + "\r\n"
     }
 }
 
@@ -139,7 +373,7 @@ impl ResponseHeader {
 mod test {
     use std::println;
 
-    use super::RequestHeader;
+    use super::{RequestHeader, ResponseHeader, MAX_HEADERS, MAX_REQUEST_LINE};
 
     #[test]
     fn header_request_test1() {
@@ -170,4 +404,72 @@ GET / HTTP/1.1\r\nHost: 127.0.0.1:8001\r\nUpgrade: websocket\r\nConnection: Upgr
 
         assert_eq!(header1, header2);
     }
+
+    #[test]
+    fn parse_request_head() {
+        let raw = b"GET /chat?x=1 HTTP/1.1\r\nHost: example.com\r\nsec-websocket-key: a: b\r\nX-Tag:one\r\nx-tag: two \r\n\r\n\x81\x00";
+        let (req, len) = RequestHeader::parse(raw).unwrap().unwrap();
+        assert_eq!(len, raw.len() - 2);
+        assert_eq!((req.method(), req.path()), ("GET", "/chat"));
+        assert_eq!(req.get("Sec-WebSocket-Key").unwrap(), "a: b");
+        assert_eq!(req.get_all("X-TAG"), vec!["one", "two"]);
+        assert!(req.format().contains("sec-websocket-key: a: b\r\n"));
+
+        // bare LF and leading empty line
+        let (req, _) = RequestHeader::parse(b"\r\nGET / HTTP/1.1\nHost: x\n\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(req.get("host").unwrap(), "x");
+
+        assert!(RequestHeader::parse(b"GET / HTTP/1.1\r\nHost: x\r\n")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn reject_invalid_request_head() {
+        let invalid = |raw: &[u8]| RequestHeader::parse(raw).is_err();
+
+        assert!(invalid(b"GET / HTTP/1.1\r\nX-Long: a\r\n  folded\r\n\r\n"));
+        assert!(invalid(b"GET / HTTP/1.1\r\nNoColon\r\n\r\n"));
+        assert!(invalid(b"GET / HTTP/1.1\r\nHost : x\r\n\r\n"));
+        assert!(invalid(b"GET /\r\n\r\n"));
+        assert!(invalid(b"GET  / HTTP/1.1\r\n\r\n"));
+
+        let long_line = format!("GET /{} HTTP/1.1", "a".repeat(MAX_REQUEST_LINE));
+        assert!(invalid(long_line.as_bytes()));
+
+        let many = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "A: b\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert!(invalid(many.as_bytes()));
+    }
+
+    #[test]
+    fn format_headers_in_order() {
+        let mut res = ResponseHeader::default();
+        res.set("Upgrade", "websocket");
+        res.set("Connection", "Upgrade");
+        res.append("Set-Cookie", "a=1");
+        res.append("Set-Cookie", "b=2");
+        res.set("Sec-WebSocket-Accept", "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        res.set("upgrade", "WebSocket");
+        assert_eq!(
+            res.format(),
+            "HTTP/1.1 101 Switching Protocols\r\n\
+             upgrade: WebSocket\r\n\
+             Connection: Upgrade\r\n\
+             Set-Cookie: a=1\r\n\
+             Set-Cookie: b=2\r\n\
+             Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+             \r\n"
+        );
+        assert_eq!(ResponseHeader::from(&res.format()), res);
+        // status line without space
+        assert_eq!(ResponseHeader::from("garbage\r\n\r\n").status(), None);
+
+        let req = RequestHeader::from("GET / HTTP/1.1\r\nHost: x\r\nA: 1\r\n\r\n");
+        assert_eq!(req.format(), "GET / HTTP/1.1\r\nHost: x\r\nA: 1\r\n\r\n");
+    }
 }
@@ -1 +1,4 @@
 pub mod header;
+pub mod reader;
+pub mod response;
+pub mod status;
@@ -0,0 +1,190 @@
+use std::{
+    io::{self, Read},
+    time::{Duration, Instant},
+};
+
+use super::header::{find_head_end, RequestHeader, ResponseHeader, MAX_HEADER_SIZE};
+
+/// default time allowed to receive a request head
+pub const HEAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// reads a request head which may arrive in several pieces
+///
+/// `timeout` is checked between reads : streams should also have a read
+/// timeout (e.g. `TcpStream::set_read_timeout`) so a silent peer can not
+/// block a single read forever
+#[derive(Debug, Clone)]
+pub struct HeadReader {
+    /// most bytes read before the head is complete
+    pub max_size: usize,
+    pub timeout: Option<Duration>,
+}
+
+impl Default for HeadReader {
+    fn default() -> Self {
+        HeadReader {
+            max_size: MAX_HEADER_SIZE,
+            timeout: Some(HEAD_TIMEOUT),
+        }
+    }
+}
+
+impl HeadReader {
+    /// read until the head is complete
+    ///
+    /// returns the head and the bytes received after it
+    /// (start of a body, or first WebSocket frames)
+    pub fn read_request<R: Read>(&self, stream: &mut R) -> io::Result<(RequestHeader, Vec<u8>)> {
+        self.read_head(stream, RequestHeader::parse)
+    }
+
+    /// read a response head (client side of a handshake)
+    ///
+    /// returns the head and the bytes received after it
+    pub fn read_response<R: Read>(&self, stream: &mut R) -> io::Result<(ResponseHeader, Vec<u8>)> {
+        self.read_head(stream, |buf| {
+            let Some(end) = find_head_end(buf) else {
+                return Ok(None);
+            };
+            let head = String::from_utf8_lossy(&buf[..end]);
+            if !head.trim_start().starts_with("HTTP/1.1 ") {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "invalid status line",
+                ));
+            }
+            Ok(Some((ResponseHeader::from(&head), end)))
+        })
+    }
+
+    fn read_head<R: Read, T>(
+        &self,
+        stream: &mut R,
+        parse: impl Fn(&[u8]) -> io::Result<Option<(T, usize)>>,
+    ) -> io::Result<(T, Vec<u8>)> {
+        let deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        let mut buf = Vec::new();
+        let mut chunk = [0u8; 1024];
+
+        loop {
+            if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "head not received in time",
+                ));
+            }
+            let len = match stream.read(&mut chunk) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before end of head",
+                    ))
+                }
+                Ok(len) => len,
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::Interrupted
+                            | io::ErrorKind::WouldBlock
+                            | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err),
+            };
+            buf.extend_from_slice(&chunk[..len]);
+
+            if let Some((head, end)) = parse(&buf)? {
+                return Ok((head, buf.split_off(end)));
+            }
+            if buf.len() > self.max_size {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "head too large"));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::http::status::StatusCode;
+
+    /// stream returning at most `size` bytes per read
+    struct Chunks {
+        data: Vec<u8>,
+        size: usize,
+    }
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = self.size.min(buf.len()).min(self.data.len());
+            buf[..len].copy_from_slice(&self.data[..len]);
+            self.data.drain(..len);
+            Ok(len)
+        }
+    }
+
+    #[test]
+    fn read_response_head() {
+        let mut stream = Chunks {
+            data: Vec::from(
+                &b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n\x81"[..],
+            ),
+            size: 5,
+        };
+        let (res, rest) = HeadReader::default().read_response(&mut stream).unwrap();
+        assert_eq!(res.status(), Some(StatusCode::SWITCHING_PROTOCOLS));
+        assert_eq!(res.get("Upgrade").unwrap(), "websocket");
+        assert_eq!(rest, b"\x81");
+
+        let mut stream = Chunks {
+            data: Vec::from(&b"garbage\r\n\r\n"[..]),
+            size: 5,
+        };
+        assert_eq!(
+            HeadReader::default()
+                .read_response(&mut stream)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+
+    #[test]
+    fn read_head_in_pieces() {
+        let mut stream = Chunks {
+            data: Vec::from(&b"GET / HTTP/1.1\r\nHost: x\r\n\r\n\x81\x00"[..]),
+            size: 4,
+        };
+        let (req, rest) = HeadReader::default().read_request(&mut stream).unwrap();
+        assert_eq!(req.get("Host").unwrap(), "x");
+        // read stopped in the middle of the frame
+        assert_eq!(rest, b"\x81");
+
+        let reader = HeadReader {
+            max_size: 8,
+            timeout: None,
+        };
+        let mut stream = Chunks {
+            data: Vec::from(&b"GET / HTTP/1.1\r\n"[..]),
+            size: 4,
+        };
+        assert_eq!(
+            reader.read_request(&mut stream).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut stream = Chunks {
+            data: Vec::from(&b"GET / HTTP/1.1\r\n"[..]),
+            size: 4,
+        };
+        assert_eq!(
+            HeadReader::default()
+                .read_request(&mut stream)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
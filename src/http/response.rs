@@ -0,0 +1,117 @@
+use std::io::{self, Write};
+
+use super::{header::ResponseHeader, status::StatusCode};
+
+/// response head and body
+///
+/// `Content-Length` is added when writing, unless the status forbids a body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    header: ResponseHeader,
+    body: Vec<u8>,
+}
+
+impl Response {
+    pub fn new(status: StatusCode) -> Self {
+        Response {
+            header: ResponseHeader::with_status(status),
+            body: Vec::new(),
+        }
+    }
+    /// plain text response
+    pub fn text(status: StatusCode, body: &str) -> Self {
+        Self::new(status)
+            .header("Content-Type", "text/plain; charset=UTF-8")
+            .body(body)
+    }
+    /// plain text response with the reason phrase as body,
+    /// the connection is closed after it
+    pub fn error(status: StatusCode) -> Self {
+        Self::text(status, status.reason()).header("Connection", "close")
+    }
+    /// replace every value of field `key`
+    pub fn header(mut self, key: &str, val: &str) -> Self {
+        self.header.set(key, val);
+        self
+    }
+    /// add a value to field `key`
+    pub fn append_header(mut self, key: &str, val: &str) -> Self {
+        self.header.append(key, val);
+        self
+    }
+    pub fn body(mut self, body: impl Into<Vec<u8>>) -> Self {
+        self.body = body.into();
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.header
+            .status()
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+    pub fn head(&self) -> &ResponseHeader {
+        &self.header
+    }
+    pub fn payload(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// head and body as sent on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut header = self.header.clone();
+        if self.status().allows_body() {
+            header.set("Content-Length", &self.body.len().to_string());
+        }
+        let mut bytes = header.format().into_bytes();
+        if self.status().allows_body() {
+            bytes.extend_from_slice(&self.body);
+        }
+        bytes
+    }
+    /// write response and flush
+    pub fn write_to<W: Write>(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_all(&self.to_bytes())?;
+        stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn build_and_write_response() {
+        let mut out = Vec::new();
+        Response::text(StatusCode::OK, "ok")
+            .header("Connection", "close")
+            .write_to(&mut out)
+            .unwrap();
+        assert_eq!(
+            out,
+            b"HTTP/1.1 200 OK\r\n\
+              Content-Type: text/plain; charset=UTF-8\r\n\
+              Connection: close\r\n\
+              Content-Length: 2\r\n\
+              \r\n\
+              ok"
+        );
+
+        let res =
+            Response::error(StatusCode::UPGRADE_REQUIRED).header("Sec-WebSocket-Version", "13");
+        let text = String::from_utf8(res.to_bytes()).unwrap();
+        assert!(text.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(text.contains("Sec-WebSocket-Version: 13\r\n"));
+        assert!(text.ends_with("\r\n\r\nUpgrade Required"));
+
+        // no body, no Content-Length
+        let res = Response::new(StatusCode::SWITCHING_PROTOCOLS)
+            .header("Upgrade", "websocket")
+            .body("ignored");
+        assert_eq!(
+            res.to_bytes(),
+            b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n"
+        );
+        assert_eq!(res.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(StatusCode::from_u16(42), None);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod http;
+pub mod net;
 pub mod socketio;
 pub mod utils;
 pub mod websockets;
@@ -0,0 +1,287 @@
+pub mod pipe;
+
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+#[cfg(unix)]
+use std::{
+    os::unix::{
+        fs::FileTypeExt,
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+};
+
+/// byte stream a server can serve : TCP or Unix domain socket
+pub trait NetStream: Read + Write + Send + Unpin + Sized + 'static {
+    /// second handle on the same socket (e.g. for a reader thread)
+    fn try_clone(&self) -> io::Result<Self>;
+    fn shutdown(&self, how: Shutdown) -> io::Result<()>;
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+    /// peer address, for logging
+    fn peer(&self) -> String;
+}
+
+/// address a `Listener` is bound to
+#[derive(Debug, Clone, PartialEq)]
+pub enum Addr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+impl Addr {
+    /// open and drop a connection, waking up a blocking `accept`
+    pub fn wake(&self) {
+        match self {
+            Addr::Tcp(addr) => drop(TcpStream::connect(addr)),
+            #[cfg(unix)]
+            Addr::Unix(path) => drop(UnixStream::connect(path)),
+        }
+    }
+}
+
+impl Display for Addr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Addr::Tcp(addr) => write!(f, "{addr}"),
+            #[cfg(unix)]
+            Addr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// stops a server started with `listen` / `serve`
+#[derive(Debug, Clone, Default)]
+pub struct ShutdownHandle {
+    stopped: Arc<AtomicBool>,
+    /// address accepting connections (used to wake up `accept`)
+    addr: Arc<Mutex<Option<Addr>>>,
+}
+
+impl ShutdownHandle {
+    /// stop accepting connections and close every socket
+    pub fn shutdown(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(addr) = &*self.addr.lock().unwrap() {
+            addr.wake();
+        }
+    }
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::SeqCst)
+    }
+    /// address woken up by `shutdown`, set by the server once bound
+    pub(crate) fn set_addr(&self, addr: Addr) {
+        *self.addr.lock().unwrap() = Some(addr);
+    }
+}
+
+/// time an accept loop waits after a failed `accept`
+/// (e.g. out of file descriptors) before trying again
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// log failed `accept` and back off, unless it was interrupted
+pub(crate) fn accept_failed(err: &io::Error) {
+    println!("ERR>> {err}");
+    if err.kind() != io::ErrorKind::Interrupted {
+        std::thread::sleep(ACCEPT_BACKOFF);
+    }
+}
+
+/// listener failing every `accept` (out of file descriptors), for tests
+/// of the accept loops
+#[cfg(test)]
+pub(crate) struct Exhausted {
+    listener: TcpListener,
+    /// calls to `accept` so far
+    pub(crate) accepts: Arc<std::sync::atomic::AtomicUsize>,
+}
+
+#[cfg(test)]
+impl Exhausted {
+    pub(crate) fn bind() -> Self {
+        Exhausted {
+            listener: TcpListener::bind("127.0.0.1:0").unwrap(),
+            accepts: Arc::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+impl Listener for Exhausted {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        self.accepts.fetch_add(1, Ordering::SeqCst);
+        Err(io::Error::from_raw_os_error(24))
+    }
+    fn local_addr(&self) -> io::Result<Addr> {
+        Listener::local_addr(&self.listener)
+    }
+}
+
+/// source of connections for the servers
+pub trait Listener: Send {
+    type Stream: NetStream;
+
+    /// wait for the next connection
+    fn accept(&self) -> io::Result<Self::Stream>;
+    fn local_addr(&self) -> io::Result<Addr>;
+}
+
+impl NetStream for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        TcpStream::shutdown(self, how)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+    fn peer(&self) -> String {
+        match self.peer_addr() {
+            Ok(addr) => addr.to_string(),
+            Err(err) => format!("unknown ({err})"),
+        }
+    }
+}
+
+impl Listener for TcpListener {
+    type Stream = TcpStream;
+
+    fn accept(&self) -> io::Result<TcpStream> {
+        TcpListener::accept(self).map(|(stream, _)| stream)
+    }
+    fn local_addr(&self) -> io::Result<Addr> {
+        TcpListener::local_addr(self).map(Addr::Tcp)
+    }
+}
+
+#[cfg(unix)]
+impl NetStream for UnixStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        UnixStream::try_clone(self)
+    }
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        UnixStream::shutdown(self, how)
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+    fn peer(&self) -> String {
+        match self.peer_addr() {
+            Ok(addr) => match addr.as_pathname() {
+                Some(path) => format!("unix:{}", path.display()),
+                None => String::from("unix:(unnamed)"),
+            },
+            Err(err) => format!("unknown ({err})"),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Listener for UnixListener {
+    type Stream = UnixStream;
+
+    fn accept(&self) -> io::Result<UnixStream> {
+        UnixListener::accept(self).map(|(stream, _)| stream)
+    }
+    fn local_addr(&self) -> io::Result<Addr> {
+        let addr = UnixListener::local_addr(self)?;
+        match addr.as_pathname() {
+            Some(path) => Ok(Addr::Unix(path.to_path_buf())),
+            None => Err(io::Error::new(
+                io::ErrorKind::AddrNotAvailable,
+                "unix listener without path",
+            )),
+        }
+    }
+}
+
+/// bind socket file `path`, replacing a stale socket left by a previous run
+///
+/// fails if the path exists and is not a socket, or if a server still
+/// accepts connections on it
+#[cfg(unix)]
+pub fn bind_unix<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+    let path = path.as_ref();
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} exists and is not a socket", path.display()),
+            ));
+        }
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("{} is in use", path.display()),
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Instant};
+
+    use super::*;
+
+    #[test]
+    fn back_off_after_failed_accept() {
+        let start = Instant::now();
+        accept_failed(&io::Error::from(io::ErrorKind::Interrupted));
+        assert!(start.elapsed() < ACCEPT_BACKOFF);
+
+        let err = Exhausted::bind().accept().unwrap_err();
+        let start = Instant::now();
+        accept_failed(&err);
+        assert!(start.elapsed() >= ACCEPT_BACKOFF);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn accept_on_unix_socket() {
+        let path = std::env::temp_dir().join(format!("sockets-net-{}.sock", std::process::id()));
+        let listener = bind_unix(&path).unwrap();
+        assert_eq!(
+            Listener::local_addr(&listener).unwrap(),
+            Addr::Unix(path.clone())
+        );
+        let client = thread::spawn({
+            let path = path.clone();
+            move || {
+                let mut stream = UnixStream::connect(path).unwrap();
+                stream.write_all(b"hello").unwrap();
+            }
+        });
+        let mut stream = Listener::accept(&listener).unwrap();
+        let mut buf = String::new();
+        stream.read_to_string(&mut buf).unwrap();
+        assert_eq!(buf, "hello");
+        client.join().unwrap();
+
+        // still served : not replaced
+        assert_eq!(
+            bind_unix(&path).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        // stale socket file is replaced
+        drop(listener);
+        let listener = bind_unix(&path).unwrap();
+        drop(listener);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
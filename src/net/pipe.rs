@@ -0,0 +1,352 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    net::Shutdown,
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use super::NetStream;
+use crate::{
+    http::status::StatusCode,
+    websockets::{
+        frame::Opcode,
+        server::WebsocketConnection,
+        util::{derive_accept_key, generate_key},
+    },
+};
+
+/// bytes travelling in one direction
+#[derive(Default)]
+struct Channel {
+    state: Mutex<Buffer>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct Buffer {
+    data: VecDeque<u8>,
+    closed: bool,
+}
+
+impl Channel {
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+}
+
+/// one end of an in-memory duplex connection, see `duplex`
+///
+/// behaves like a socket : reads block until data arrives, the peer
+/// closes (EOF) or the read timeout elapses (`WouldBlock`), and writes
+/// fail with `BrokenPipe` once the peer is gone. Reads and writes can be
+/// cut into small chunks and fail with injected errors
+pub struct Pipe {
+    inbox: Arc<Channel>,
+    outbox: Arc<Channel>,
+    /// most bytes returned by a single read
+    read_chunk: usize,
+    /// most bytes accepted by a single write
+    write_chunk: usize,
+    /// errors returned by the next reads, before any data
+    read_errors: VecDeque<io::ErrorKind>,
+    /// errors returned by the next writes
+    write_errors: VecDeque<io::ErrorKind>,
+    /// shared with clones, like the timeout of a socket
+    timeout: Arc<Mutex<Option<Duration>>>,
+    /// handles on this end : channels are closed when the last one is dropped
+    handles: Arc<()>,
+}
+
+/// connected pair of pipes
+pub fn duplex() -> (Pipe, Pipe) {
+    let (a_to_b, b_to_a) = (Arc::new(Channel::default()), Arc::new(Channel::default()));
+    (
+        Pipe::end(b_to_a.clone(), a_to_b.clone()),
+        Pipe::end(a_to_b, b_to_a),
+    )
+}
+
+impl Pipe {
+    fn end(inbox: Arc<Channel>, outbox: Arc<Channel>) -> Self {
+        Pipe {
+            inbox,
+            outbox,
+            read_chunk: usize::MAX,
+            write_chunk: usize::MAX,
+            read_errors: VecDeque::new(),
+            write_errors: VecDeque::new(),
+            timeout: Arc::new(Mutex::new(None)),
+            handles: Arc::new(()),
+        }
+    }
+    /// return at most `size` bytes per read
+    pub fn read_chunk(mut self, size: usize) -> Self {
+        self.read_chunk = size.max(1);
+        self
+    }
+    /// accept at most `size` bytes per write (short writes)
+    pub fn write_chunk(mut self, size: usize) -> Self {
+        self.write_chunk = size.max(1);
+        self
+    }
+    /// fail the next read (after the already injected ones) with `kind`
+    pub fn inject_read_error(&mut self, kind: io::ErrorKind) {
+        self.read_errors.push_back(kind);
+    }
+    /// fail the next write (after the already injected ones) with `kind`
+    pub fn inject_write_error(&mut self, kind: io::ErrorKind) {
+        self.write_errors.push_back(kind);
+    }
+    /// bytes written by the peer and not read yet
+    pub fn available(&self) -> usize {
+        self.inbox.state.lock().unwrap().data.len()
+    }
+}
+
+impl Read for Pipe {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(kind) = self.read_errors.pop_front() {
+            return Err(io::Error::new(kind, "injected read error"));
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = self.timeout.lock().unwrap().map(|t| Instant::now() + t);
+
+        let mut state = self.inbox.state.lock().unwrap();
+        while state.data.is_empty() && !state.closed {
+            state = match deadline {
+                None => self.inbox.ready.wait(state).unwrap(),
+                Some(deadline) => {
+                    let Some(remain) = deadline.checked_duration_since(Instant::now()) else {
+                        return Err(io::Error::new(io::ErrorKind::WouldBlock, "read timed out"));
+                    };
+                    self.inbox.ready.wait_timeout(state, remain).unwrap().0
+                }
+            };
+        }
+
+        let len = buf.len().min(self.read_chunk).min(state.data.len());
+        for (dst, src) in buf.iter_mut().zip(state.data.drain(..len)) {
+            *dst = src;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(kind) = self.write_errors.pop_front() {
+            return Err(io::Error::new(kind, "injected write error"));
+        }
+        let mut state = self.outbox.state.lock().unwrap();
+        if state.closed {
+            return Err(io::Error::new(io::ErrorKind::BrokenPipe, "pipe closed"));
+        }
+        let len = buf.len().min(self.write_chunk);
+        state.data.extend(&buf[..len]);
+        self.outbox.ready.notify_all();
+        Ok(len)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        if Arc::strong_count(&self.handles) == 1 {
+            self.inbox.close();
+            self.outbox.close();
+        }
+    }
+}
+
+impl NetStream for Pipe {
+    /// new handle on the same end, chunking is kept but not injected errors
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(Pipe {
+            inbox: self.inbox.clone(),
+            outbox: self.outbox.clone(),
+            read_chunk: self.read_chunk,
+            write_chunk: self.write_chunk,
+            read_errors: VecDeque::new(),
+            write_errors: VecDeque::new(),
+            timeout: self.timeout.clone(),
+            handles: self.handles.clone(),
+        })
+    }
+    fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        if matches!(how, Shutdown::Read | Shutdown::Both) {
+            self.inbox.close();
+        }
+        if matches!(how, Shutdown::Write | Shutdown::Both) {
+            self.outbox.close();
+        }
+        Ok(())
+    }
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        *self.timeout.lock().unwrap() = timeout;
+        Ok(())
+    }
+    fn peer(&self) -> String {
+        String::from("pipe")
+    }
+}
+
+/// websocket upgrade over a pipe : returns the server connection and the
+/// client connection (which masks the frames it sends)
+///
+/// chunking and injected errors of both ends already apply to the handshake
+pub fn connect_websocket(
+    server: Pipe,
+    mut client: Pipe,
+    max_size: Option<usize>,
+) -> io::Result<(WebsocketConnection<Pipe>, WebsocketConnection<Pipe>)> {
+    let key = generate_key();
+    let request = format!(
+        "GET / HTTP/1.1\r\nHost: pipe\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+    );
+    client.write_all(request.as_bytes())?;
+
+    let mut server = WebsocketConnection::new(server, max_size);
+    server.handshake()?;
+
+    // response head, byte by byte so that no frame is read
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        if client.read(&mut byte)? == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "closed during handshake",
+            ));
+        }
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head);
+    let accept = format!(
+        "Sec-WebSocket-Accept: {}\r\n",
+        derive_accept_key(key.as_bytes())
+    );
+    let status = format!("HTTP/1.1 {}\r\n", StatusCode::SWITCHING_PROTOCOLS);
+    if !head.starts_with(&status) || !head.contains(&accept) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unexpected handshake response {head:?}"),
+        ));
+    }
+
+    let mut client = WebsocketConnection::client(client, max_size);
+    client.connection.connect();
+    Ok((server, client))
+}
+
+/// client frame (masked with a random key, as RFC 6455 requires)
+pub fn masked_frame(opcode: Opcode, payload: &[u8]) -> Vec<u8> {
+    let mask: [u8; 4] = rand::random();
+    let mut raw = vec![0b1000_0000 | opcode.format()];
+    match payload.len() {
+        len @ 0..=125 => raw.push(0b1000_0000 | len as u8),
+        len @ 126..=0xffff => {
+            raw.push(0b1000_0000 | 126);
+            raw.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            raw.push(0b1000_0000 | 127);
+            raw.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    raw.extend_from_slice(&mask);
+    raw.extend(payload.iter().enumerate().map(|(idx, b)| b ^ mask[idx % 4]));
+    raw
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+
+    use super::*;
+    use crate::websockets::frame::Data;
+
+    #[test]
+    fn pipe_chunks_errors_and_close() {
+        let (a, b) = duplex();
+        let mut a = a.write_chunk(3);
+        let mut b = b.read_chunk(2);
+
+        assert_eq!(a.write(b"hello").unwrap(), 3);
+        a.write_all(b"lo").unwrap();
+        let mut buf = [0u8; 8];
+        assert_eq!(b.read(&mut buf).unwrap(), 2);
+
+        b.inject_read_error(io::ErrorKind::Interrupted);
+        assert_eq!(
+            b.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::Interrupted
+        );
+        let mut rest = Vec::new();
+        let reader = thread::spawn(move || {
+            b.read_to_end(&mut rest).unwrap();
+            rest
+        });
+        a.write_all(b"!").unwrap();
+        drop(a);
+        assert_eq!(reader.join().unwrap(), b"llo!");
+
+        let (a, mut b) = duplex();
+        b.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        assert_eq!(
+            b.read(&mut buf).unwrap_err().kind(),
+            io::ErrorKind::WouldBlock
+        );
+        drop(a);
+        assert_eq!(b.write(b"x").unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn websocket_over_pipe() {
+        let (server, client) = duplex();
+        // every read returns a single byte and is interrupted once
+        let mut server = server.read_chunk(1);
+        server.inject_read_error(io::ErrorKind::Interrupted);
+        let (mut server, mut client) = connect_websocket(server, client, None).unwrap();
+
+        client
+            .stream
+            .write_all(&masked_frame(Opcode::Data(Data::Text), b"ping"))
+            .unwrap();
+        let big = vec![7u8; 300];
+        client
+            .stream
+            .write_all(&masked_frame(Opcode::Data(Data::Binary), &big))
+            .unwrap();
+
+        let frame = server.try_receive().unwrap();
+        assert_eq!(frame.payload, b"ping");
+        let frame = server.try_receive().unwrap();
+        assert_eq!(
+            (frame.header.opcode, frame.payload),
+            (Opcode::Data(Data::Binary), big)
+        );
+
+        server.send_msg(String::from("pong")).unwrap();
+        let frame = client.try_receive().unwrap();
+        assert_eq!(frame.payload, b"pong");
+
+        drop(client);
+        assert_eq!(
+            server.try_receive().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+        // peer is gone : writes fail instead of panicking
+        assert_eq!(
+            server.send_msg(String::from("late")).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert!(server.send_binary(vec![1]).is_err());
+        assert!(server.send_pong().is_err());
+    }
+}
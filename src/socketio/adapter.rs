@@ -0,0 +1,198 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+/// which sockets a broadcast reaches
+///
+/// sockets in any of `rooms` (every socket when empty),
+/// minus sockets in any of `except`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BroadcastOptions {
+    pub rooms: HashSet<String>,
+    pub except: HashSet<String>,
+}
+
+/// writes a packet and its binary attachments to the local socket with given id
+pub type Deliver = Arc<dyn Fn(&str, &str, &[Vec<u8>]) + Send + Sync>;
+
+/// keeps track of rooms and delivers broadcasts
+///
+/// every socket is expected to be in the room named by its own id,
+/// so `except(id)` excludes a single socket
+pub trait Adapter: Send {
+    /// set the function used to write to local sockets
+    fn init(&mut self, deliver: Deliver);
+    /// join socket to rooms
+    fn add_all(&mut self, id: &str, rooms: &[&str]);
+    /// leave socket from room (room is removed when empty)
+    fn del(&mut self, id: &str, room: &str);
+    /// leave socket from every room
+    fn del_all(&mut self, id: &str);
+    /// rooms joined by local socket
+    fn socket_rooms(&self, id: &str) -> HashSet<String>;
+    /// local socket ids matching options
+    fn sockets(&self, opts: &BroadcastOptions) -> HashSet<String>;
+    /// send packet (and its attachments) to every socket matching options
+    fn broadcast(&mut self, packet: &str, attachments: &[Vec<u8>], opts: &BroadcastOptions);
+    /// socket ids matching options on every server sharing this adapter
+    fn fetch_sockets(&self, opts: &BroadcastOptions) -> HashSet<String> {
+        self.sockets(opts)
+    }
+}
+
+/// in-process adapter (default)
+#[derive(Default)]
+pub struct MemoryAdapter {
+    /// room -> socket ids
+    rooms: HashMap<String, HashSet<String>>,
+    /// socket id -> rooms
+    sids: HashMap<String, HashSet<String>>,
+
+    deliver: Option<Deliver>,
+}
+
+impl MemoryAdapter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// every room with at least one socket
+    pub fn rooms(&self) -> HashSet<String> {
+        self.rooms.keys().cloned().collect()
+    }
+}
+
+impl Adapter for MemoryAdapter {
+    fn init(&mut self, deliver: Deliver) {
+        self.deliver = Some(deliver);
+    }
+    fn add_all(&mut self, id: &str, rooms: &[&str]) {
+        for room in rooms {
+            self.rooms
+                .entry(String::from(*room))
+                .or_default()
+                .insert(String::from(id));
+            self.sids
+                .entry(String::from(id))
+                .or_default()
+                .insert(String::from(*room));
+        }
+    }
+    fn del(&mut self, id: &str, room: &str) {
+        if let Some(members) = self.rooms.get_mut(room) {
+            members.remove(id);
+            if members.is_empty() {
+                self.rooms.remove(room);
+            }
+        }
+        if let Some(rooms) = self.sids.get_mut(id) {
+            rooms.remove(room);
+            if rooms.is_empty() {
+                self.sids.remove(id);
+            }
+        }
+    }
+    fn del_all(&mut self, id: &str) {
+        if let Some(rooms) = self.sids.remove(id) {
+            for room in rooms {
+                if let Some(members) = self.rooms.get_mut(&room) {
+                    members.remove(id);
+                    if members.is_empty() {
+                        self.rooms.remove(&room);
+                    }
+                }
+            }
+        }
+    }
+    fn socket_rooms(&self, id: &str) -> HashSet<String> {
+        self.sids.get(id).cloned().unwrap_or_default()
+    }
+    fn sockets(&self, opts: &BroadcastOptions) -> HashSet<String> {
+        let excluded: HashSet<&String> = opts
+            .except
+            .iter()
+            .filter_map(|room| self.rooms.get(room))
+            .flatten()
+            .collect();
+
+        let candidates: Vec<&String> = if opts.rooms.is_empty() {
+            self.sids.keys().collect()
+        } else {
+            opts.rooms
+                .iter()
+                .filter_map(|room| self.rooms.get(room))
+                .flatten()
+                .collect()
+        };
+
+        candidates
+            .into_iter()
+            .filter(|id| !excluded.contains(id))
+            .cloned()
+            .collect()
+    }
+    fn broadcast(&mut self, packet: &str, attachments: &[Vec<u8>], opts: &BroadcastOptions) {
+        if let Some(deliver) = &self.deliver {
+            for id in self.sockets(opts) {
+                deliver(&id, packet, attachments)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    fn opts(rooms: &[&str], except: &[&str]) -> BroadcastOptions {
+        BroadcastOptions {
+            rooms: rooms.iter().map(|r| String::from(*r)).collect(),
+            except: except.iter().map(|r| String::from(*r)).collect(),
+        }
+    }
+
+    #[test]
+    fn memory_adapter_rooms() {
+        let mut adapter = MemoryAdapter::new();
+        adapter.add_all("a", &["a", "red", "blue"]);
+        adapter.add_all("b", &["b", "blue"]);
+
+        assert_eq!(adapter.sockets(&opts(&["red", "blue"], &[])).len(), 2);
+        assert_eq!(
+            adapter.sockets(&opts(&["blue"], &["red"])),
+            HashSet::from([String::from("b")])
+        );
+        assert_eq!(adapter.sockets(&opts(&[], &["a"])).len(), 1);
+
+        adapter.del("a", "red");
+        assert!(!adapter.rooms().contains("red"));
+
+        adapter.del_all("b");
+        assert_eq!(
+            adapter.rooms(),
+            HashSet::from([String::from("a"), String::from("blue")])
+        );
+        assert!(adapter.socket_rooms("b").is_empty());
+    }
+
+    #[test]
+    fn memory_adapter_broadcast() {
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let sink = delivered.clone();
+
+        let mut adapter = MemoryAdapter::new();
+        adapter.init(Arc::new(move |id, packet, attachments| {
+            sink.lock()
+                .unwrap()
+                .push(format!("{id}:{packet}:{}", attachments.len()))
+        }));
+        adapter.add_all("a", &["a", "red"]);
+        adapter.add_all("b", &["b"]);
+
+        adapter.broadcast("42[\"x\"]", &[], &opts(&["red"], &[]));
+
+        assert_eq!(*delivered.lock().unwrap(), vec!["a:42[\"x\"]:0"]);
+    }
+}
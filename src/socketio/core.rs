@@ -1,49 +1,232 @@
-use std::sync::mpsc;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::mpsc,
+};
 
-type T<'a> = Box<dyn Send + 'a>;
+/// index of a unit (connection thread)
+pub type ConnectionId = usize;
 
-pub struct Unit<T> {
-    id: usize,
-    pub sender: mpsc::Sender<T>,
-    pub receiver: mpsc::Receiver<T>,
+/// message exchanged between units and their commander
+#[derive(Debug, Clone, PartialEq)]
+pub enum Signal {
+    /// unit -> commander : unit took a client
+    Connect { id: ConnectionId },
+    /// unit -> commander : unit lost its client (leaves every room),
+    /// echoed back to the unit once handled
+    Disconnect { id: ConnectionId },
+    /// unit -> commander : join room
+    Join { id: ConnectionId, room: String },
+    /// unit -> commander : leave room
+    Leave { id: ConnectionId, room: String },
+    /// unit -> commander : send `msg` to every connection in `room`
+    /// (every connection when `None`) but the sender
+    Broadcast {
+        from: ConnectionId,
+        room: Option<String>,
+        msg: String,
+    },
+    /// unit -> commander : send `msg` to connection `to`,
+    /// dropped when `to` is not connected
+    Send {
+        from: ConnectionId,
+        to: ConnectionId,
+        msg: String,
+    },
+    /// unit -> commander : ask for `Stats`
+    StatsRequest { id: ConnectionId },
+    /// commander -> unit : message to write to the client
+    Deliver { from: ConnectionId, msg: String },
+    /// commander -> unit : answer of `StatsRequest`
+    Stats(Stats),
 }
 
-pub struct Commander<T> {
-    pub senders: Vec<mpsc::Sender<T>>,
-    pub receiver: mpsc::Receiver<T>,
+/// state of the commander
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stats {
+    /// connected units
+    pub connections: usize,
+    /// rooms with at least one connection
+    pub rooms: usize,
+    /// signals routed so far
+    pub routed: u64,
 }
 
-pub struct Army<T> {
-    pub units: Vec<Option<Unit<T>>>,
+pub struct Unit {
+    pub id: ConnectionId,
+    pub sender: mpsc::Sender<Signal>,
+    pub receiver: mpsc::Receiver<Signal>,
+}
+
+pub struct Commander {
+    pub senders: Vec<mpsc::Sender<Signal>>,
+    pub receiver: mpsc::Receiver<Signal>,
+
+    /// room -> connection ids
+    rooms: HashMap<String, HashSet<ConnectionId>>,
+    connected: HashSet<ConnectionId>,
+    routed: u64,
+}
+
+pub struct Army {
+    pub units: Vec<Option<Unit>>,
 
-    pub commander: Commander<T>,
+    pub commander: Commander,
 }
 
-impl Unit<T<'_>> {
-    pub fn build<'a>(
-        id: usize,
-        sender: mpsc::Sender<T<'a>>,
-        receiver: mpsc::Receiver<T<'a>>,
-    ) -> Unit<T<'a>> {
+type SendResult = Result<(), mpsc::SendError<Signal>>;
+
+impl Unit {
+    pub fn build(
+        id: ConnectionId,
+        sender: mpsc::Sender<Signal>,
+        receiver: mpsc::Receiver<Signal>,
+    ) -> Unit {
         Unit {
             id,
             sender,
             receiver,
         }
     }
+    pub fn connect(&self) -> SendResult {
+        self.sender.send(Signal::Connect { id: self.id })
+    }
+    pub fn disconnect(&self) -> SendResult {
+        self.sender.send(Signal::Disconnect { id: self.id })
+    }
+    pub fn join(&self, room: &str) -> SendResult {
+        self.sender.send(Signal::Join {
+            id: self.id,
+            room: String::from(room),
+        })
+    }
+    pub fn leave(&self, room: &str) -> SendResult {
+        self.sender.send(Signal::Leave {
+            id: self.id,
+            room: String::from(room),
+        })
+    }
+    /// send to every connection in `room` (every connection when `None`) but self
+    pub fn broadcast(&self, room: Option<&str>, msg: &str) -> SendResult {
+        self.sender.send(Signal::Broadcast {
+            from: self.id,
+            room: room.map(String::from),
+            msg: String::from(msg),
+        })
+    }
+    pub fn send_to(&self, to: ConnectionId, msg: &str) -> SendResult {
+        self.sender.send(Signal::Send {
+            from: self.id,
+            to,
+            msg: String::from(msg),
+        })
+    }
+    pub fn request_stats(&self) -> SendResult {
+        self.sender.send(Signal::StatsRequest { id: self.id })
+    }
 }
 
-impl Commander<T<'_>> {
-    pub fn build<'a>(
-        senders: Vec<mpsc::Sender<T<'a>>>,
-        receiver: mpsc::Receiver<T<'a>>,
-    ) -> Commander<T<'a>> {
-        Commander { senders, receiver }
+impl Commander {
+    pub fn build(
+        senders: Vec<mpsc::Sender<Signal>>,
+        receiver: mpsc::Receiver<Signal>,
+    ) -> Commander {
+        Commander {
+            senders,
+            receiver,
+            rooms: HashMap::new(),
+            connected: HashSet::new(),
+            routed: 0,
+        }
+    }
+
+    /// route signals until every unit is dropped
+    pub fn run(mut self) {
+        while let Ok(signal) = self.receiver.recv() {
+            self.dispatch(signal);
+        }
+    }
+
+    /// route a single signal received from a unit
+    pub fn dispatch(&mut self, signal: Signal) {
+        self.routed += 1;
+        match signal {
+            Signal::Connect { id } => {
+                self.connected.insert(id);
+            }
+            Signal::Disconnect { id } => {
+                self.connected.remove(&id);
+                self.rooms.retain(|_, members| {
+                    members.remove(&id);
+                    !members.is_empty()
+                });
+                self.deliver(id, Signal::Disconnect { id });
+            }
+            Signal::Join { id, room } => {
+                self.rooms.entry(room).or_default().insert(id);
+            }
+            Signal::Leave { id, room } => {
+                if let Some(members) = self.rooms.get_mut(&room) {
+                    members.remove(&id);
+                    if members.is_empty() {
+                        self.rooms.remove(&room);
+                    }
+                }
+            }
+            Signal::Broadcast { from, room, msg } => {
+                let targets: Vec<ConnectionId> = match &room {
+                    Some(room) => match self.rooms.get(room) {
+                        Some(members) => members.iter().copied().collect(),
+                        None => Vec::new(),
+                    },
+                    None => self.connected.iter().copied().collect(),
+                };
+                for to in targets.into_iter().filter(|to| *to != from) {
+                    self.deliver(
+                        to,
+                        Signal::Deliver {
+                            from,
+                            msg: msg.clone(),
+                        },
+                    );
+                }
+            }
+            Signal::Send { from, to, msg } => {
+                // slots are reused : `to` may be the id of a client that left
+                if self.connected.contains(&to) {
+                    self.deliver(to, Signal::Deliver { from, msg });
+                } else {
+                    println!("ERR>> unit {to} is not connected, message of {from} dropped");
+                }
+            }
+            Signal::StatsRequest { id } => self.deliver(id, Signal::Stats(self.stats())),
+            Signal::Deliver { .. } | Signal::Stats(_) => {
+                println!("ERR>> commander received a unit signal: {signal:?}")
+            }
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            connections: self.connected.len(),
+            rooms: self.rooms.len(),
+            routed: self.routed,
+        }
+    }
+
+    fn deliver(&self, to: ConnectionId, signal: Signal) {
+        match self.senders.get(to) {
+            Some(sender) => {
+                if sender.send(signal).is_err() {
+                    println!("ERR>> unit {to} is gone");
+                }
+            }
+            None => println!("ERR>> unknown unit {to}"),
+        }
     }
 }
 
-impl Army<T<'_>> {
-    pub fn build<'a>(size: usize) -> Army<T<'a>> {
+impl Army {
+    pub fn build(size: usize) -> Army {
         let mut units = Vec::with_capacity(size);
 
         let mut senders = Vec::with_capacity(size);
@@ -64,3 +247,95 @@ impl Army<T<'_>> {
         Army { units, commander }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// route every signal sent so far
+    fn route(commander: &mut Commander) {
+        while let Ok(signal) = commander.receiver.try_recv() {
+            commander.dispatch(signal);
+        }
+    }
+
+    fn delivered(unit: &Unit) -> Vec<Signal> {
+        unit.receiver.try_iter().collect()
+    }
+
+    #[test]
+    fn route_by_connection_id() {
+        let mut army = Army::build(3);
+        let units: Vec<Unit> = army.units.iter_mut().map(|u| u.take().unwrap()).collect();
+        let commander = &mut army.commander;
+
+        for unit in &units {
+            unit.connect().unwrap();
+        }
+        units[0].join("red").unwrap();
+        units[1].join("red").unwrap();
+        route(commander);
+
+        units[0].broadcast(Some("red"), "to red").unwrap();
+        units[2].broadcast(None, "to all").unwrap();
+        units[2].send_to(0, "direct").unwrap();
+        route(commander);
+
+        let deliver = |from, msg: &str| Signal::Deliver {
+            from,
+            msg: String::from(msg),
+        };
+        assert_eq!(
+            delivered(&units[0]),
+            vec![deliver(2, "to all"), deliver(2, "direct")]
+        );
+        assert_eq!(
+            delivered(&units[1]),
+            vec![deliver(0, "to red"), deliver(2, "to all")]
+        );
+        assert!(delivered(&units[2]).is_empty());
+
+        units[1].leave("red").unwrap();
+        units[0].disconnect().unwrap();
+        units[2].request_stats().unwrap();
+        route(commander);
+
+        assert_eq!(delivered(&units[0]), vec![Signal::Disconnect { id: 0 }]);
+        match delivered(&units[2]).as_slice() {
+            [Signal::Stats(stats)] => {
+                assert_eq!((stats.connections, stats.rooms), (2, 0));
+            }
+            other => panic!("unexpected signals {other:?}"),
+        }
+    }
+
+    #[test]
+    fn drop_messages_to_disconnected_units() {
+        let mut army = Army::build(2);
+        let units: Vec<Unit> = army.units.iter_mut().map(|u| u.take().unwrap()).collect();
+        let commander = &mut army.commander;
+
+        units[0].connect().unwrap();
+        units[1].connect().unwrap();
+        units[0].disconnect().unwrap();
+        route(commander);
+        assert_eq!(delivered(&units[0]), vec![Signal::Disconnect { id: 0 }]);
+
+        // sent to the client that left, routed before the slot is reused
+        units[1].send_to(0, "stale").unwrap();
+        route(commander);
+        units[0].connect().unwrap();
+        route(commander);
+        assert!(delivered(&units[0]).is_empty());
+
+        units[1].send_to(0, "fresh").unwrap();
+        route(commander);
+        assert_eq!(
+            delivered(&units[0]),
+            vec![Signal::Deliver {
+                from: 1,
+                msg: String::from("fresh")
+            }]
+        );
+    }
+}
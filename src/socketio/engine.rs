@@ -0,0 +1,447 @@
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    utils::base64::Base64,
+    websockets::{message::Message, server::WebsocketConnection},
+};
+
+/// separates packets in a polling payload
+pub const RECORD_SEPARATOR: char = '\x1e';
+
+/// default time between two pings (ms)
+pub const PING_INTERVAL: u64 = 25000;
+/// default time the client has to answer a ping (ms)
+pub const PING_TIMEOUT: u64 = 20000;
+
+/// Engine.IO (v4) packet types
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PacketType {
+    Open,
+    Close,
+    Ping,
+    Pong,
+    Message,
+    Upgrade,
+    Noop,
+}
+
+impl PacketType {
+    fn parse(char: char) -> Option<Self> {
+        let kind = match char {
+            '0' => PacketType::Open,
+            '1' => PacketType::Close,
+            '2' => PacketType::Ping,
+            '3' => PacketType::Pong,
+            '4' => PacketType::Message,
+            '5' => PacketType::Upgrade,
+            '6' => PacketType::Noop,
+            _ => return None,
+        };
+        Some(kind)
+    }
+    fn format(&self) -> char {
+        match self {
+            PacketType::Open => '0',
+            PacketType::Close => '1',
+            PacketType::Ping => '2',
+            PacketType::Pong => '3',
+            PacketType::Message => '4',
+            PacketType::Upgrade => '5',
+            PacketType::Noop => '6',
+        }
+    }
+}
+
+/// Engine.IO packet : type character followed by data
+#[derive(Debug, PartialEq, Clone)]
+pub struct Packet {
+    pub kind: PacketType,
+    pub data: String,
+}
+
+impl Packet {
+    pub fn new(kind: PacketType, data: &str) -> Self {
+        Packet {
+            kind,
+            data: String::from(data),
+        }
+    }
+    pub fn parse(str: &str) -> Option<Self> {
+        let mut chars = str.chars();
+        let kind = PacketType::parse(chars.next()?)?;
+        Some(Packet {
+            kind,
+            data: String::from(chars.as_str()),
+        })
+    }
+}
+
+impl Display for Packet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.kind.format(), self.data)
+    }
+}
+
+/// join encoded packets into a polling payload
+pub fn encode_payload(packets: &[String]) -> String {
+    packets.join(&RECORD_SEPARATOR.to_string())
+}
+
+/// split polling payload into encoded packets
+pub fn decode_payload(payload: &str) -> Vec<&str> {
+    payload
+        .split(RECORD_SEPARATOR)
+        .filter(|packet| !packet.is_empty())
+        .collect()
+}
+
+/// open packet sent on handshake
+pub fn open_packet(
+    sid: &str,
+    upgrades: &[&str],
+    max_payload_size: usize,
+    ping_interval: Duration,
+    ping_timeout: Duration,
+) -> Packet {
+    let upgrades: Vec<String> = upgrades.iter().map(|u| format!("{u:?}")).collect();
+    let data = format!(
+        "{{\"sid\":\"{sid}\",\"upgrades\":[{}],\"pingInterval\":{},\"pingTimeout\":{},\"maxPayload\":{max_payload_size}}}",
+        upgrades.join(","),
+        ping_interval.as_millis(),
+        ping_timeout.as_millis(),
+    );
+    Packet::new(PacketType::Open, &data)
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Transport {
+    Polling,
+    Websocket,
+}
+
+pub(crate) struct SessionState<Stream> {
+    /// writer once the session runs over websocket
+    pub(crate) websocket: Option<WebsocketConnection<Stream>>,
+    /// encoded packets waiting for the next poll
+    pub(crate) queue: Vec<String>,
+    pub(crate) closed: bool,
+    /// ping waiting for its pong
+    pub(crate) pinged: Option<Instant>,
+    /// last pong received (or session start)
+    pub(crate) alive: Instant,
+}
+
+/// outgoing side of an Engine.IO session
+///
+/// packets are written to the websocket directly, or buffered
+/// until the client polls while the session runs over polling
+pub struct Session<Stream> {
+    pub sid: String,
+    pub(crate) state: Mutex<SessionState<Stream>>,
+    ready: Condvar,
+}
+
+impl<Stream> Session<Stream>
+where
+    Stream: Read + Write + Unpin,
+{
+    pub fn polling(sid: &str) -> Self {
+        Self::build(sid, None)
+    }
+    pub fn websocket(sid: &str, wc: WebsocketConnection<Stream>) -> Self {
+        Self::build(sid, Some(wc))
+    }
+    fn build(sid: &str, websocket: Option<WebsocketConnection<Stream>>) -> Self {
+        Session {
+            sid: String::from(sid),
+            state: Mutex::new(SessionState {
+                websocket,
+                queue: Vec::new(),
+                closed: false,
+                pinged: None,
+                alive: Instant::now(),
+            }),
+            ready: Condvar::new(),
+        }
+    }
+
+    pub fn transport(&self) -> Transport {
+        match self.state.lock().unwrap().websocket {
+            Some(_) => Transport::Websocket,
+            None => Transport::Polling,
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// send encoded packet through current transport
+    pub fn send(&self, packet: String) -> io::Result<()> {
+        self.send_with(packet, &[])
+    }
+
+    /// send encoded packet followed by binary attachments
+    ///
+    /// attachments are sent as binary frames over websocket,
+    /// or as `b` prefixed base64 packets over polling.
+    /// A failed write closes the session
+    pub fn send_with(&self, packet: String, attachments: &[Vec<u8>]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("session {} is closed", self.sid),
+            ));
+        }
+        match state.websocket.as_mut() {
+            Some(wc) => {
+                let written = wc.send_msg(packet).and_then(|_| {
+                    attachments
+                        .iter()
+                        .try_for_each(|data| wc.send_binary(data.clone()))
+                });
+                if written.is_err() {
+                    state.closed = true;
+                }
+                written
+            }
+            None => {
+                state.queue.push(packet);
+                for data in attachments {
+                    state.queue.push(format!("b{}", Base64.encode(data)));
+                }
+                self.ready.notify_all();
+                Ok(())
+            }
+        }
+    }
+
+    /// write websocket message (pong, close answer) through the writer
+    /// shared with packets
+    ///
+    /// a close is sent once, nothing is written over polling
+    pub fn send_message(&self, message: Message) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let Some(wc) = state.websocket.as_mut() else {
+            return Ok(());
+        };
+        if let (Message::Close(_), true) = (&message, wc.is_closing()) {
+            return Ok(());
+        }
+        let written = wc.send_message(message);
+        if written.is_err() {
+            state.closed = true;
+        }
+        written
+    }
+
+    /// wait until packets are buffered (at most `timeout`) and take them
+    ///
+    /// returns a ping when nothing was sent in time,
+    /// and a close packet once the session is closed
+    pub fn poll(&self, timeout: Duration) -> Vec<String> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+
+        while state.queue.is_empty() && !state.closed {
+            let Some(remain) = deadline.checked_duration_since(Instant::now()) else {
+                state.pinged.get_or_insert_with(Instant::now);
+                return vec![Packet::new(PacketType::Ping, "").to_string()];
+            };
+            state = self.ready.wait_timeout(state, remain).unwrap().0;
+        }
+
+        let mut packets: Vec<String> = state.queue.drain(..).collect();
+        if state.closed {
+            packets.push(Packet::new(PacketType::Close, "").to_string());
+        }
+        packets
+    }
+
+    /// ping the client once `interval` passed since its last pong
+    ///
+    /// returns false when the ping was not answered within `timeout`,
+    /// or the session was closed (e.g. by a failed write)
+    pub fn heartbeat(&self, interval: Duration, timeout: Duration) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return false;
+        }
+        match state.pinged {
+            Some(pinged) => pinged.elapsed() < timeout,
+            None => {
+                if state.alive.elapsed() < interval {
+                    return true;
+                }
+                state.pinged = Some(Instant::now());
+                drop(state);
+                self.send(Packet::new(PacketType::Ping, "").to_string())
+                    .is_ok()
+            }
+        }
+    }
+
+    /// pong received : the client is alive
+    pub fn pong(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.pinged = None;
+        state.alive = Instant::now();
+    }
+
+    /// switch to websocket, flushing buffered packets through it
+    ///
+    /// noop packets only end the pending poll and are dropped.
+    /// A failed write closes the session
+    pub fn upgrade(&self, mut wc: WebsocketConnection<Stream>) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let noop = Packet::new(PacketType::Noop, "").to_string();
+        let written = state
+            .queue
+            .drain(..)
+            .filter(|packet| *packet != noop)
+            .try_for_each(|packet| wc.send_msg(packet));
+        if written.is_err() {
+            state.closed = true;
+        }
+        state.websocket = Some(wc);
+        self.ready.notify_all();
+        written
+    }
+
+    /// close session, waking up pending poll
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        self.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io::Cursor, sync::Arc, thread};
+
+    use super::*;
+    use crate::net::pipe::duplex;
+
+    #[test]
+    fn packet_format_and_parse() {
+        let packet = Packet::parse("42[\"event\",\"data\"]").unwrap();
+        assert_eq!(packet.kind, PacketType::Message);
+        assert_eq!(packet.data, "2[\"event\",\"data\"]");
+        assert_eq!(packet.to_string(), "42[\"event\",\"data\"]");
+
+        assert_eq!(
+            Packet::parse("2probe").unwrap(),
+            Packet::new(PacketType::Ping, "probe")
+        );
+        assert_eq!(Packet::parse("6").unwrap().kind, PacketType::Noop);
+        assert!(Packet::parse("").is_none());
+        assert!(Packet::parse("9").is_none());
+
+        let open = open_packet(
+            "sid",
+            &["websocket"],
+            1000,
+            Duration::from_millis(PING_INTERVAL),
+            Duration::from_millis(PING_TIMEOUT),
+        );
+        assert!(open
+            .to_string()
+            .starts_with("0{\"sid\":\"sid\",\"upgrades\":[\"websocket\"]"));
+        assert!(open
+            .to_string()
+            .ends_with("\"pingInterval\":25000,\"pingTimeout\":20000,\"maxPayload\":1000}"));
+    }
+
+    #[test]
+    fn payload_batching() {
+        let packets = vec![
+            String::from("4hello"),
+            String::from("2"),
+            String::from("4€"),
+        ];
+        let payload = encode_payload(&packets);
+        assert_eq!(payload, "4hello\x1e2\x1e4€");
+        assert_eq!(decode_payload(&payload), packets);
+        assert_eq!(decode_payload("4a"), vec!["4a"]);
+    }
+
+    #[test]
+    fn session_poll_and_upgrade() {
+        let session: Arc<Session<Cursor<Vec<u8>>>> = Arc::new(Session::polling("sid"));
+        assert_eq!(session.poll(Duration::ZERO), vec!["2"]);
+
+        let sender = session.clone();
+        let thd = thread::spawn(move || sender.send(String::from("4wake")));
+        assert_eq!(session.poll(Duration::from_secs(5)), vec!["4wake"]);
+        thd.join().unwrap().unwrap();
+
+        session
+            .send_with(String::from("451-[\"x\"]"), &[vec![1, 2]])
+            .unwrap();
+        assert_eq!(session.poll(Duration::ZERO), vec!["451-[\"x\"]", "bAQI="]);
+
+        session.send(String::from("4a")).unwrap();
+        session.send(String::from("6")).unwrap();
+        session.send(String::from("4b")).unwrap();
+        session
+            .upgrade(WebsocketConnection::new(Cursor::new(Vec::new()), None))
+            .unwrap();
+        session.send(String::from("4c")).unwrap();
+        assert_eq!(session.transport(), Transport::Websocket);
+
+        let state = session.state.lock().unwrap();
+        let written = state.websocket.as_ref().unwrap().stream.get_ref();
+        // three text frames of 2 bytes each
+        assert_eq!(written, b"\x81\x024a\x81\x024b\x81\x024c");
+        drop(state);
+
+        session.close();
+        assert_eq!(session.poll(Duration::from_secs(5)), vec!["1"]);
+    }
+
+    #[test]
+    fn close_session_on_failed_write() {
+        let (server, client) = duplex();
+        let session = Session::websocket("sid", WebsocketConnection::new(server, None));
+        session.send(String::from("4a")).unwrap();
+
+        drop(client);
+        assert_eq!(
+            session.send(String::from("4b")).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+        assert!(session.is_closed());
+        assert_eq!(
+            session.send(String::from("4c")).unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+        assert!(!session.heartbeat(Duration::ZERO, Duration::ZERO));
+    }
+
+    #[test]
+    fn session_heartbeat() {
+        let session: Session<Cursor<Vec<u8>>> = Session::polling("sid");
+        let (interval, timeout) = (Duration::from_millis(20), Duration::from_millis(40));
+
+        assert!(session.heartbeat(interval, timeout));
+        assert_eq!(session.poll(Duration::ZERO), vec!["2"]);
+        session.pong();
+
+        thread::sleep(interval);
+        assert!(session.heartbeat(interval, timeout));
+        assert_eq!(session.poll(Duration::from_secs(5)), vec!["2"]);
+        // no pong
+        thread::sleep(timeout);
+        assert!(!session.heartbeat(interval, timeout));
+
+        session.pong();
+        assert!(session.heartbeat(interval, timeout));
+    }
+}
@@ -0,0 +1,642 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::{self, Cursor, Read, Write},
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::adapter::{Adapter, BroadcastOptions, Deliver, MemoryAdapter};
+use crate::net::bind_unix;
+
+/// default time to wait for other servers to answer `fetch_sockets`
+const FETCH_TIMEOUT: u64 = 200;
+/// largest frame accepted from the hub (64 MB)
+const MAX_FRAME: usize = 64 * 1024 * 1024;
+/// frames the hub queues for a server that does not read them, the
+/// server is disconnected beyond that
+const PEER_QUEUE: usize = 256;
+
+//  Every message between a server and the hub is a frame of
+//  u32 (big endian) length followed by the body.
+//  The hub forwards frames from one server to every other server as is,
+//  and answers a fetch with the number of servers it was forwarded to.
+//
+//  body : tag (u8) + fields
+//  string, bytes : u32 length + bytes
+//  set, list : u32 count + items
+
+const TAG_BROADCAST: u8 = 0;
+const TAG_FETCH: u8 = 1;
+const TAG_SOCKETS: u8 = 2;
+const TAG_EXPECT: u8 = 3;
+
+#[derive(Debug, PartialEq)]
+enum Message {
+    Broadcast {
+        opts: BroadcastOptions,
+        packet: String,
+        attachments: Vec<Vec<u8>>,
+    },
+    FetchSockets {
+        request: u64,
+        opts: BroadcastOptions,
+    },
+    Sockets {
+        request: u64,
+        ids: HashSet<String>,
+    },
+    /// sent by the hub : number of `Sockets` answers to wait for
+    Expect {
+        request: u64,
+        replies: u32,
+    },
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_frame(output: &mut impl Write, body: &[u8]) -> io::Result<()> {
+    output.write_all(&(body.len() as u32).to_be_bytes())?;
+    output.write_all(body)?;
+    output.flush()
+}
+
+fn read_frame(input: &mut impl Read) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_FRAME {
+        return Err(invalid("frame too large"));
+    }
+    let mut body = vec![0u8; len];
+    input.read_exact(&mut body)?;
+    Ok(body)
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+    out.extend_from_slice(bytes);
+}
+
+fn put_str(out: &mut Vec<u8>, str: &str) {
+    put_bytes(out, str.as_bytes())
+}
+
+fn put_set(out: &mut Vec<u8>, set: &HashSet<String>) {
+    out.extend_from_slice(&(set.len() as u32).to_be_bytes());
+    for str in set {
+        put_str(out, str);
+    }
+}
+
+fn get_u32(cursor: &mut Cursor<&[u8]>) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    cursor.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn get_u64(cursor: &mut Cursor<&[u8]>) -> io::Result<u64> {
+    let mut buf = [0u8; 8];
+    cursor.read_exact(&mut buf)?;
+    Ok(u64::from_be_bytes(buf))
+}
+
+fn get_bytes(cursor: &mut Cursor<&[u8]>) -> io::Result<Vec<u8>> {
+    let len = get_u32(cursor)? as usize;
+    let remain = cursor.get_ref().len() - cursor.position() as usize;
+    if len > remain {
+        return Err(invalid("field longer than message"));
+    }
+    let mut buf = vec![0u8; len];
+    cursor.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn get_str(cursor: &mut Cursor<&[u8]>) -> io::Result<String> {
+    String::from_utf8(get_bytes(cursor)?).map_err(|_| invalid("string is not utf-8"))
+}
+
+fn get_set(cursor: &mut Cursor<&[u8]>) -> io::Result<HashSet<String>> {
+    let count = get_u32(cursor)?;
+    let mut set = HashSet::new();
+    for _ in 0..count {
+        set.insert(get_str(cursor)?);
+    }
+    Ok(set)
+}
+
+impl Message {
+    fn format(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Message::Broadcast {
+                opts,
+                packet,
+                attachments,
+            } => {
+                out.push(TAG_BROADCAST);
+                put_set(&mut out, &opts.rooms);
+                put_set(&mut out, &opts.except);
+                put_str(&mut out, packet);
+                out.extend_from_slice(&(attachments.len() as u32).to_be_bytes());
+                for data in attachments {
+                    put_bytes(&mut out, data);
+                }
+            }
+            Message::FetchSockets { request, opts } => {
+                out.push(TAG_FETCH);
+                out.extend_from_slice(&request.to_be_bytes());
+                put_set(&mut out, &opts.rooms);
+                put_set(&mut out, &opts.except);
+            }
+            Message::Sockets { request, ids } => {
+                out.push(TAG_SOCKETS);
+                out.extend_from_slice(&request.to_be_bytes());
+                put_set(&mut out, ids);
+            }
+            Message::Expect { request, replies } => {
+                out.push(TAG_EXPECT);
+                out.extend_from_slice(&request.to_be_bytes());
+                out.extend_from_slice(&replies.to_be_bytes());
+            }
+        }
+        out
+    }
+
+    fn parse(body: &[u8]) -> io::Result<Self> {
+        let (tag, fields) = body.split_first().ok_or(invalid("empty message"))?;
+        let mut cursor = Cursor::new(fields);
+        let msg = match *tag {
+            TAG_BROADCAST => Message::Broadcast {
+                opts: BroadcastOptions {
+                    rooms: get_set(&mut cursor)?,
+                    except: get_set(&mut cursor)?,
+                },
+                packet: get_str(&mut cursor)?,
+                attachments: {
+                    let count = get_u32(&mut cursor)?;
+                    let mut list = Vec::new();
+                    for _ in 0..count {
+                        list.push(get_bytes(&mut cursor)?);
+                    }
+                    list
+                },
+            },
+            TAG_FETCH => Message::FetchSockets {
+                request: get_u64(&mut cursor)?,
+                opts: BroadcastOptions {
+                    rooms: get_set(&mut cursor)?,
+                    except: get_set(&mut cursor)?,
+                },
+            },
+            TAG_SOCKETS => Message::Sockets {
+                request: get_u64(&mut cursor)?,
+                ids: get_set(&mut cursor)?,
+            },
+            TAG_EXPECT => Message::Expect {
+                request: get_u64(&mut cursor)?,
+                replies: get_u32(&mut cursor)?,
+            },
+            _ => return Err(invalid("unknown message")),
+        };
+        Ok(msg)
+    }
+}
+
+/// server connected to the hub
+struct Peer {
+    stream: UnixStream,
+    /// frames written to the server by its own thread, so that a slow
+    /// server never blocks the others (bounded by `PEER_QUEUE`)
+    queue: mpsc::SyncSender<Arc<Vec<u8>>>,
+}
+
+/// relay between servers on one machine
+///
+/// listens on a unix domain socket and forwards every frame
+/// received from one server to all the others
+pub struct IpcHub {
+    path: PathBuf,
+    peers: Arc<Mutex<HashMap<usize, Peer>>>,
+    closed: Arc<AtomicBool>,
+}
+
+impl IpcHub {
+    /// bind hub to socket file (a stale socket is replaced)
+    ///
+    /// fails if the path is not a socket or another hub still listens on it
+    pub fn bind(path: impl AsRef<Path>) -> io::Result<IpcHub> {
+        let path = path.as_ref().to_path_buf();
+        let listener = bind_unix(&path)?;
+        let peers: Arc<Mutex<HashMap<usize, Peer>>> = Arc::new(Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let (acc_peers, acc_closed) = (peers.clone(), closed.clone());
+        thread::spawn(move || {
+            for (id, stream) in listener.incoming().enumerate() {
+                if acc_closed.load(Ordering::SeqCst) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let (Ok(mut writer), Ok(shutdown)) = (stream.try_clone(), stream.try_clone())
+                else {
+                    continue;
+                };
+                let (queue, frames) = mpsc::sync_channel::<Arc<Vec<u8>>>(PEER_QUEUE);
+                thread::spawn(move || {
+                    for body in frames {
+                        if write_frame(&mut writer, &body).is_err() {
+                            break;
+                        }
+                    }
+                });
+                let peer = Peer {
+                    stream: shutdown,
+                    queue,
+                };
+                acc_peers.lock().unwrap().insert(id, peer);
+
+                let peers = acc_peers.clone();
+                thread::spawn(move || Self::relay(id, stream, peers));
+            }
+        });
+
+        Ok(IpcHub {
+            path,
+            peers,
+            closed,
+        })
+    }
+
+    fn relay(id: usize, mut stream: UnixStream, peers: Arc<Mutex<HashMap<usize, Peer>>>) {
+        while let Ok(body) = read_frame(&mut stream) {
+            let body = Arc::new(body);
+            let mut peers = peers.lock().unwrap();
+            let mut forwarded = 0;
+            let mut stalled = Vec::new();
+            for (peer, output) in peers.iter() {
+                if *peer == id {
+                    continue;
+                }
+                // a dead peer is removed by its own relay thread
+                match output.queue.try_send(body.clone()) {
+                    Ok(()) => forwarded += 1,
+                    Err(mpsc::TrySendError::Full(_)) => stalled.push(*peer),
+                    Err(mpsc::TrySendError::Disconnected(_)) => {}
+                }
+            }
+            if body.first() == Some(&TAG_FETCH) {
+                if let (Ok(Message::FetchSockets { request, .. }), Some(own)) =
+                    (Message::parse(&body), peers.get(&id))
+                {
+                    let expect = Message::Expect {
+                        request,
+                        replies: forwarded,
+                    };
+                    if let Err(mpsc::TrySendError::Full(_)) =
+                        own.queue.try_send(Arc::new(expect.format()))
+                    {
+                        stalled.push(id);
+                    }
+                }
+            }
+            for peer in stalled {
+                Self::disconnect(&mut peers, peer);
+            }
+        }
+        peers.lock().unwrap().remove(&id);
+    }
+
+    /// drop server that does not read its frames : its queue is freed and
+    /// its relay thread ends on the closed socket
+    fn disconnect(peers: &mut HashMap<usize, Peer>, id: usize) {
+        if let Some(peer) = peers.remove(&id) {
+            println!("ERR>> ipc server {id} does not read its frames, disconnected");
+            let _ = peer.stream.shutdown(std::net::Shutdown::Both);
+        }
+    }
+
+    /// number of connected servers
+    pub fn peers(&self) -> usize {
+        self.peers.lock().unwrap().len()
+    }
+}
+
+impl Drop for IpcHub {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // wake up accept loop
+        let _ = UnixStream::connect(&self.path);
+        for peer in self.peers.lock().unwrap().values() {
+            let _ = peer.stream.shutdown(std::net::Shutdown::Both);
+        }
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// adapter sharing broadcasts between servers through an `IpcHub`
+///
+/// rooms are kept locally; broadcasts are applied locally and
+/// forwarded to other servers which apply them to their own sockets
+pub struct IpcAdapter {
+    local: Arc<Mutex<MemoryAdapter>>,
+    writer: Arc<Mutex<UnixStream>>,
+    reader: Option<UnixStream>,
+
+    pending: Arc<Mutex<HashMap<u64, mpsc::Sender<Message>>>>,
+    timeout: Duration,
+}
+
+impl IpcAdapter {
+    /// connect to hub listening on socket file
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<IpcAdapter> {
+        let stream = UnixStream::connect(path)?;
+        Ok(IpcAdapter {
+            local: Arc::new(Mutex::new(MemoryAdapter::new())),
+            writer: Arc::new(Mutex::new(stream.try_clone()?)),
+            reader: Some(stream),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            timeout: Duration::from_millis(FETCH_TIMEOUT),
+        })
+    }
+    /// set time to wait for other servers to answer `fetch_sockets`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn publish(&self, msg: &Message) -> io::Result<()> {
+        write_frame(&mut *self.writer.lock().unwrap(), &msg.format())
+    }
+
+    fn listen(
+        mut reader: UnixStream,
+        local: Arc<Mutex<MemoryAdapter>>,
+        writer: Arc<Mutex<UnixStream>>,
+        pending: Arc<Mutex<HashMap<u64, mpsc::Sender<Message>>>>,
+    ) {
+        while let Ok(body) = read_frame(&mut reader) {
+            match Message::parse(&body) {
+                Ok(Message::Broadcast {
+                    opts,
+                    packet,
+                    attachments,
+                }) => {
+                    local
+                        .lock()
+                        .unwrap()
+                        .broadcast(&packet, &attachments, &opts);
+                }
+                Ok(Message::FetchSockets { request, opts }) => {
+                    let ids = local.lock().unwrap().sockets(&opts);
+                    let msg = Message::Sockets { request, ids };
+                    let _ = write_frame(&mut *writer.lock().unwrap(), &msg.format());
+                }
+                Ok(msg @ (Message::Sockets { request, .. } | Message::Expect { request, .. })) => {
+                    if let Some(tx) = pending.lock().unwrap().get(&request) {
+                        let _ = tx.send(msg);
+                    }
+                }
+                Err(err) => println!("ERR>> ipc: {err}"),
+            }
+        }
+    }
+}
+
+/// unique id across servers : pid in upper bits, counter in lower bits
+fn request_id() -> u64 {
+    static REQUESTS: AtomicU64 = AtomicU64::new(0);
+    (u64::from(std::process::id()) << 32) | (REQUESTS.fetch_add(1, Ordering::SeqCst) & 0xFFFF_FFFF)
+}
+
+impl Adapter for IpcAdapter {
+    fn init(&mut self, deliver: Deliver) {
+        self.local.lock().unwrap().init(deliver);
+        if let Some(reader) = self.reader.take() {
+            let (local, writer, pending) = (
+                self.local.clone(),
+                self.writer.clone(),
+                self.pending.clone(),
+            );
+            thread::spawn(move || Self::listen(reader, local, writer, pending));
+        }
+    }
+    fn add_all(&mut self, id: &str, rooms: &[&str]) {
+        self.local.lock().unwrap().add_all(id, rooms)
+    }
+    fn del(&mut self, id: &str, room: &str) {
+        self.local.lock().unwrap().del(id, room)
+    }
+    fn del_all(&mut self, id: &str) {
+        self.local.lock().unwrap().del_all(id)
+    }
+    fn socket_rooms(&self, id: &str) -> HashSet<String> {
+        self.local.lock().unwrap().socket_rooms(id)
+    }
+    fn sockets(&self, opts: &BroadcastOptions) -> HashSet<String> {
+        self.local.lock().unwrap().sockets(opts)
+    }
+    fn broadcast(&mut self, packet: &str, attachments: &[Vec<u8>], opts: &BroadcastOptions) {
+        self.local
+            .lock()
+            .unwrap()
+            .broadcast(packet, attachments, opts);
+        // hub is gone : behave as a single server
+        let _ = self.publish(&Message::Broadcast {
+            opts: opts.clone(),
+            packet: String::from(packet),
+            attachments: attachments.to_vec(),
+        });
+    }
+    fn fetch_sockets(&self, opts: &BroadcastOptions) -> HashSet<String> {
+        let request = request_id();
+        let (tx, rx) = mpsc::channel();
+        self.pending.lock().unwrap().insert(request, tx);
+
+        let published = self.publish(&Message::FetchSockets {
+            request,
+            opts: opts.clone(),
+        });
+
+        let mut ids = self.sockets(opts);
+        // answers expected (known once the hub tells) and received
+        let (mut expected, mut received) = (None, 0);
+        let deadline = Instant::now() + self.timeout;
+        while published.is_ok() && expected != Some(received) {
+            let Some(remain) = deadline.checked_duration_since(Instant::now()) else {
+                break;
+            };
+            match rx.recv_timeout(remain) {
+                Ok(Message::Sockets { ids: remote, .. }) => {
+                    ids.extend(remote);
+                    received += 1;
+                }
+                Ok(Message::Expect { replies, .. }) => expected = Some(replies),
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
+
+        self.pending.lock().unwrap().remove(&request);
+        ids
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn message_format_and_parse() {
+        let msgs = [
+            Message::Broadcast {
+                opts: BroadcastOptions {
+                    rooms: HashSet::from([String::from("room")]),
+                    except: HashSet::from([String::from("sid"), String::from("other")]),
+                },
+                packet: String::from("42[\"event\",\"\\u0000\"]"),
+                attachments: vec![vec![], vec![0, 1, 2]],
+            },
+            Message::FetchSockets {
+                request: u64::MAX,
+                opts: BroadcastOptions::default(),
+            },
+            Message::Sockets {
+                request: 7,
+                ids: HashSet::from([String::from("a")]),
+            },
+            Message::Expect {
+                request: 7,
+                replies: 2,
+            },
+        ];
+        for msg in msgs {
+            assert_eq!(Message::parse(&msg.format()).unwrap(), msg);
+        }
+
+        assert!(Message::parse(&[]).is_err());
+        assert!(Message::parse(&[TAG_SOCKETS, 0, 0]).is_err());
+        assert!(Message::parse(&[TAG_BROADCAST, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff]).is_err());
+        // missing attachment count
+        assert!(Message::parse(&[TAG_BROADCAST, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sockets-ipc-{name}-{}.sock", std::process::id()))
+    }
+
+    fn wait_until(cond: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Instant::now() < deadline {
+            if cond() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    #[test]
+    fn bind_keeps_files_and_live_hubs() {
+        let path = socket_path("bind");
+        fs::write(&path, b"not a socket").unwrap();
+        assert_eq!(
+            IpcHub::bind(&path).err().unwrap().kind(),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(fs::read(&path).unwrap(), b"not a socket");
+        fs::remove_file(&path).unwrap();
+
+        let hub = IpcHub::bind(&path).unwrap();
+        let _adapter = IpcAdapter::connect(&path).unwrap();
+        assert_eq!(
+            IpcHub::bind(&path).err().unwrap().kind(),
+            io::ErrorKind::AddrInUse
+        );
+        assert!(wait_until(|| hub.peers() == 1));
+    }
+
+    #[test]
+    fn fetch_sockets_returns_once_every_server_answered() {
+        let path = socket_path("fetch");
+        let hub = IpcHub::bind(&path).unwrap();
+        let adapters: Vec<IpcAdapter> = (0..3)
+            .map(|idx| {
+                let mut adapter = IpcAdapter::connect(&path)
+                    .unwrap()
+                    .with_timeout(Duration::from_secs(30));
+                adapter.init(Arc::new(|_, _, _| {}));
+                let id = format!("s{idx}");
+                adapter.add_all(&id, &[&id, "room"]);
+                adapter
+            })
+            .collect();
+        assert!(wait_until(|| hub.peers() == 3));
+
+        let start = Instant::now();
+        let opts = BroadcastOptions {
+            rooms: HashSet::from([String::from("room")]),
+            except: HashSet::new(),
+        };
+        assert_eq!(adapters[0].fetch_sockets(&opts).len(), 3);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // alone on the hub : nobody to wait for
+        drop(hub);
+        let hub = IpcHub::bind(&path).unwrap();
+        let mut alone = IpcAdapter::connect(&path)
+            .unwrap()
+            .with_timeout(Duration::from_secs(30));
+        alone.init(Arc::new(|_, _, _| {}));
+        alone.add_all("s", &["s", "room"]);
+        assert!(wait_until(|| hub.peers() == 1));
+        let start = Instant::now();
+        assert_eq!(alone.fetch_sockets(&opts).len(), 1);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn slow_server_does_not_block_relay() {
+        let path = socket_path("slow");
+        let hub = IpcHub::bind(&path).unwrap();
+
+        // connected, never reads
+        let mut stuck = UnixStream::connect(&path).unwrap();
+        let mut sender = IpcAdapter::connect(&path).unwrap();
+        sender.init(Arc::new(|_, _, _| {}));
+
+        let delivered = Arc::new(AtomicU64::new(0));
+        let mut receiver = IpcAdapter::connect(&path).unwrap();
+        receiver.init({
+            let delivered = delivered.clone();
+            Arc::new(move |_, _, _| {
+                delivered.fetch_add(1, Ordering::SeqCst);
+            })
+        });
+        receiver.add_all("b", &["b"]);
+        assert!(wait_until(|| hub.peers() == 3));
+
+        // far more than the socket buffer and the queue of the stuck server
+        let packet = "x".repeat(64 * 1024);
+        let sent = 2 * PEER_QUEUE as u64;
+        for _ in 0..sent {
+            sender.broadcast(&packet, &[], &BroadcastOptions::default());
+        }
+        assert!(wait_until(|| delivered.load(Ordering::SeqCst) == sent));
+        // stuck server is disconnected instead of queueing every frame
+        assert_eq!(hub.peers(), 2);
+        let mut rest = Vec::new();
+        stuck
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stuck.read_to_end(&mut rest).unwrap();
+    }
+}
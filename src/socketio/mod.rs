@@ -1,4 +1,9 @@
+pub mod adapter;
 pub mod core;
+pub mod engine;
+#[cfg(unix)]
+pub mod ipc;
+pub mod packet;
 pub mod server;
 pub mod server2;
 pub mod server_async;
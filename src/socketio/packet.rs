@@ -0,0 +1,698 @@
+use std::{fmt::Display, io, iter::Peekable, str::Chars};
+
+/// most binary attachments accepted in a single packet
+const MAX_ATTACHMENTS: usize = 10;
+
+/// deepest nesting accepted in a json payload
+const MAX_DEPTH: usize = 64;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// json value which may also hold byte buffers
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// members in insertion order
+    Object(Vec<(String, Value)>),
+    /// sent as a binary attachment
+    Binary(Vec<u8>),
+}
+
+impl From<&str> for Value {
+    fn from(str: &str) -> Self {
+        Value::String(String::from(str))
+    }
+}
+
+impl From<f64> for Value {
+    fn from(num: f64) -> Self {
+        Value::Number(num)
+    }
+}
+
+impl From<Vec<u8>> for Value {
+    fn from(data: Vec<u8>) -> Self {
+        Value::Binary(data)
+    }
+}
+
+fn write_str(f: &mut std::fmt::Formatter<'_>, str: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for char in str.chars() {
+        match char {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{c}")?,
+        }
+    }
+    write!(f, "\"")
+}
+
+impl Display for Value {
+    /// json text (byte buffers are written as arrays of numbers)
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Bool(bool) => write!(f, "{bool}"),
+            Value::Number(num) if num.is_finite() => write!(f, "{num}"),
+            Value::Number(_) => write!(f, "null"),
+            Value::String(str) => write_str(f, str),
+            Value::Array(items) => {
+                write!(f, "[")?;
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Value::Object(members) => {
+                write!(f, "{{")?;
+                for (idx, (key, val)) in members.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{val}")?;
+                }
+                write!(f, "}}")
+            }
+            Value::Binary(data) => {
+                let items: Vec<Value> = data.iter().map(|b| Value::Number(*b as f64)).collect();
+                write!(f, "{}", Value::Array(items))
+            }
+        }
+    }
+}
+
+impl Value {
+    /// parse json text
+    pub fn parse(str: &str) -> io::Result<Self> {
+        let mut chars = str.chars().peekable();
+        let value = Self::__parse_value(&mut chars, 0)?;
+        Self::__skip_whitespace(&mut chars);
+        match chars.next() {
+            None => Ok(value),
+            Some(_) => Err(invalid("trailing characters after json")),
+        }
+    }
+
+    /// `{"_placeholder":true,"num":n}`
+    fn placeholder(num: usize) -> Self {
+        Value::Object(vec![
+            (String::from("_placeholder"), Value::Bool(true)),
+            (String::from("num"), Value::Number(num as f64)),
+        ])
+    }
+
+    fn placeholder_num(&self) -> Option<usize> {
+        let Value::Object(members) = self else {
+            return None;
+        };
+        let flag = members.iter().find(|(key, _)| key == "_placeholder");
+        let num = members.iter().find(|(key, _)| key == "num");
+        match (flag, num) {
+            (Some((_, Value::Bool(true))), Some((_, Value::Number(num))))
+                if *num >= 0.0 && num.fract() == 0.0 =>
+            {
+                Some(*num as usize)
+            }
+            _ => None,
+        }
+    }
+
+    /// true when a byte buffer is nested in this value
+    pub fn has_binary(&self) -> bool {
+        match self {
+            Value::Binary(_) => true,
+            Value::Array(items) => items.iter().any(Value::has_binary),
+            Value::Object(members) => members.iter().any(|(_, val)| val.has_binary()),
+            _ => false,
+        }
+    }
+
+    /// replace byte buffers with placeholders, moving them into `buffers`
+    fn deconstruct(self, buffers: &mut Vec<Vec<u8>>) -> Self {
+        match self {
+            Value::Binary(data) => {
+                buffers.push(data);
+                Self::placeholder(buffers.len() - 1)
+            }
+            Value::Array(items) => {
+                Value::Array(items.into_iter().map(|v| v.deconstruct(buffers)).collect())
+            }
+            Value::Object(members) => Value::Object(
+                members
+                    .into_iter()
+                    .map(|(key, val)| (key, val.deconstruct(buffers)))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// replace placeholders with the matching buffer
+    fn reconstruct(self, buffers: &[Vec<u8>]) -> io::Result<Self> {
+        if let Some(num) = self.placeholder_num() {
+            return match buffers.get(num) {
+                Some(data) => Ok(Value::Binary(data.clone())),
+                None => Err(invalid("placeholder without attachment")),
+            };
+        }
+        let value = match self {
+            Value::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .map(|v| v.reconstruct(buffers))
+                    .collect::<io::Result<_>>()?,
+            ),
+            Value::Object(members) => Value::Object(
+                members
+                    .into_iter()
+                    .map(|(key, val)| Ok((key, val.reconstruct(buffers)?)))
+                    .collect::<io::Result<_>>()?,
+            ),
+            other => other,
+        };
+        Ok(value)
+    }
+
+    fn __skip_whitespace(chars: &mut Peekable<Chars>) {
+        while matches!(chars.peek(), Some(' ' | '\t' | '\n' | '\r')) {
+            chars.next();
+        }
+    }
+
+    fn __expect(chars: &mut Peekable<Chars>, word: &str) -> io::Result<()> {
+        for expected in word.chars() {
+            if chars.next() != Some(expected) {
+                return Err(invalid("unexpected character in json"));
+            }
+        }
+        Ok(())
+    }
+
+    fn __parse_value(chars: &mut Peekable<Chars>, depth: usize) -> io::Result<Self> {
+        if depth > MAX_DEPTH {
+            return Err(invalid("json nested too deep"));
+        }
+        Self::__skip_whitespace(chars);
+        match chars.peek() {
+            Some('n') => Self::__expect(chars, "null").map(|_| Value::Null),
+            Some('t') => Self::__expect(chars, "true").map(|_| Value::Bool(true)),
+            Some('f') => Self::__expect(chars, "false").map(|_| Value::Bool(false)),
+            Some('"') => Self::__parse_string(chars).map(Value::String),
+            Some('[') => {
+                chars.next();
+                let mut items = Vec::new();
+                Self::__skip_whitespace(chars);
+                if chars.peek() == Some(&']') {
+                    chars.next();
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(Self::__parse_value(chars, depth + 1)?);
+                    Self::__skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some(']') => return Ok(Value::Array(items)),
+                        _ => return Err(invalid("expected ',' or ']' in json array")),
+                    }
+                }
+            }
+            Some('{') => {
+                chars.next();
+                let mut members = Vec::new();
+                Self::__skip_whitespace(chars);
+                if chars.peek() == Some(&'}') {
+                    chars.next();
+                    return Ok(Value::Object(members));
+                }
+                loop {
+                    Self::__skip_whitespace(chars);
+                    let key = Self::__parse_string(chars)?;
+                    Self::__skip_whitespace(chars);
+                    if chars.next() != Some(':') {
+                        return Err(invalid("expected ':' in json object"));
+                    }
+                    members.push((key, Self::__parse_value(chars, depth + 1)?));
+                    Self::__skip_whitespace(chars);
+                    match chars.next() {
+                        Some(',') => continue,
+                        Some('}') => return Ok(Value::Object(members)),
+                        _ => return Err(invalid("expected ',' or '}' in json object")),
+                    }
+                }
+            }
+            Some('-' | '0'..='9') => Self::__parse_number(chars),
+            _ => Err(invalid("unexpected character in json")),
+        }
+    }
+
+    fn __parse_number(chars: &mut Peekable<Chars>) -> io::Result<Self> {
+        let mut text = String::new();
+        while let Some(char) = chars.peek() {
+            match char {
+                '-' | '+' | '.' | 'e' | 'E' | '0'..='9' => {
+                    text.push(*char);
+                    chars.next();
+                }
+                _ => break,
+            }
+        }
+        // rust accepts a superset of json numbers (e.g. "1." or "+1")
+        let digits = text.trim_start_matches('-');
+        let leading_zero =
+            digits.starts_with('0') && digits[1..].starts_with(|c: char| c.is_ascii_digit());
+        let bad_fraction = text.ends_with('.') || text.contains(".e") || text.contains(".E");
+        let valid = digits.starts_with(|c: char| c.is_ascii_digit())
+            && !text.starts_with('+')
+            && !leading_zero
+            && !bad_fraction;
+        match text.parse::<f64>() {
+            Ok(num) if valid => Ok(Value::Number(num)),
+            _ => Err(invalid("invalid number in json")),
+        }
+    }
+
+    fn __parse_hex4(chars: &mut Peekable<Chars>) -> io::Result<u32> {
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = chars
+                .next()
+                .and_then(|c| c.to_digit(16))
+                .ok_or(invalid("invalid unicode escape in json"))?;
+            code = code * 16 + digit;
+        }
+        Ok(code)
+    }
+
+    fn __parse_string(chars: &mut Peekable<Chars>) -> io::Result<String> {
+        if chars.next() != Some('"') {
+            return Err(invalid("expected string in json"));
+        }
+        let mut out = String::new();
+        loop {
+            match chars.next() {
+                None => return Err(invalid("unterminated string in json")),
+                Some('"') => return Ok(out),
+                Some('\\') => {
+                    let escaped = match chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let high = Self::__parse_hex4(chars)?;
+                            let code = if (0xD800..0xDC00).contains(&high) {
+                                Self::__expect(chars, "\\u")?;
+                                let low = Self::__parse_hex4(chars)?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(invalid("invalid surrogate pair in json"));
+                                }
+                                0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                            } else {
+                                high
+                            };
+                            char::from_u32(code).ok_or(invalid("invalid unicode escape in json"))?
+                        }
+                        _ => return Err(invalid("invalid escape in json")),
+                    };
+                    out.push(escaped);
+                }
+                Some(c) if (c as u32) < 0x20 => {
+                    return Err(invalid("control character in json string"))
+                }
+                Some(c) => out.push(c),
+            }
+        }
+    }
+}
+
+/// Socket.IO (v5) packet types
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum PacketKind {
+    Connect,
+    Disconnect,
+    Event,
+    Ack,
+    ConnectError,
+    BinaryEvent,
+    BinaryAck,
+}
+
+impl PacketKind {
+    fn parse(char: char) -> Option<Self> {
+        let kind = match char {
+            '0' => PacketKind::Connect,
+            '1' => PacketKind::Disconnect,
+            '2' => PacketKind::Event,
+            '3' => PacketKind::Ack,
+            '4' => PacketKind::ConnectError,
+            '5' => PacketKind::BinaryEvent,
+            '6' => PacketKind::BinaryAck,
+            _ => return None,
+        };
+        Some(kind)
+    }
+    fn format(&self) -> char {
+        match self {
+            PacketKind::Connect => '0',
+            PacketKind::Disconnect => '1',
+            PacketKind::Event => '2',
+            PacketKind::Ack => '3',
+            PacketKind::ConnectError => '4',
+            PacketKind::BinaryEvent => '5',
+            PacketKind::BinaryAck => '6',
+        }
+    }
+}
+
+//  <kind>[<attachments>-][<namespace>,][<ack id>][<json data>]
+//
+//  e.g. `2["hello",1]`
+//       `51-/admin,3["file",{"_placeholder":true,"num":0}]`
+
+/// Socket.IO packet
+///
+/// byte buffers in `data` are sent as binary attachments,
+/// so `Event`/`Ack` become `BinaryEvent`/`BinaryAck` on the wire
+#[derive(Debug, PartialEq, Clone)]
+pub struct Packet {
+    pub kind: PacketKind,
+    pub nsp: String,
+    pub id: Option<u64>,
+    pub data: Option<Value>,
+}
+
+impl Packet {
+    /// event packet on default namespace : `[name, args...]`
+    pub fn event(name: &str, args: Vec<Value>) -> Self {
+        let mut data = vec![Value::from(name)];
+        data.extend(args);
+        Packet {
+            kind: PacketKind::Event,
+            nsp: String::from("/"),
+            id: None,
+            data: Some(Value::Array(data)),
+        }
+    }
+
+    /// event name and arguments
+    pub fn event_args(&self) -> Option<(&str, &[Value])> {
+        match (&self.kind, &self.data) {
+            (PacketKind::Event | PacketKind::BinaryEvent, Some(Value::Array(items))) => {
+                match items.split_first() {
+                    Some((Value::String(name), args)) => Some((name, args)),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// encode packet, taking byte buffers out as attachments
+    pub fn encode(&self) -> (String, Vec<Vec<u8>>) {
+        let mut buffers = Vec::new();
+        let data = self.data.clone().map(|data| data.deconstruct(&mut buffers));
+
+        let kind = match (self.kind, buffers.is_empty()) {
+            (PacketKind::Event, false) => PacketKind::BinaryEvent,
+            (PacketKind::Ack, false) => PacketKind::BinaryAck,
+            (kind, _) => kind,
+        };
+
+        let mut out = String::from(kind.format());
+        if matches!(kind, PacketKind::BinaryEvent | PacketKind::BinaryAck) {
+            out += &format!("{}-", buffers.len());
+        }
+        if self.nsp != "/" {
+            out += &format!("{},", self.nsp);
+        }
+        if let Some(id) = self.id {
+            out += &id.to_string();
+        }
+        if let Some(data) = data {
+            out += &data.to_string();
+        }
+        (out, buffers)
+    }
+
+    /// decode packet text, returning the number of attachments to follow
+    pub fn decode(str: &str) -> io::Result<(Self, usize)> {
+        let mut chars = str.chars().peekable();
+        let kind = chars
+            .next()
+            .and_then(PacketKind::parse)
+            .ok_or(invalid("unknown packet type"))?;
+
+        let mut attachments = 0;
+        if matches!(kind, PacketKind::BinaryEvent | PacketKind::BinaryAck) {
+            let mut digits = String::new();
+            while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                digits.push(c);
+            }
+            if chars.next() != Some('-') {
+                return Err(invalid("missing attachment count"));
+            }
+            attachments = digits
+                .parse::<usize>()
+                .map_err(|_| invalid("invalid attachment count"))?;
+            if attachments > MAX_ATTACHMENTS {
+                return Err(invalid("too many attachments"));
+            }
+        }
+
+        let mut nsp = String::from("/");
+        if chars.peek() == Some(&'/') {
+            nsp.clear();
+            for c in chars.by_ref() {
+                if c == ',' {
+                    break;
+                }
+                nsp.push(c);
+            }
+        }
+
+        let mut digits = String::new();
+        while let Some(c) = chars.next_if(char::is_ascii_digit) {
+            digits.push(c);
+        }
+        let id = match digits.is_empty() {
+            true => None,
+            false => Some(
+                digits
+                    .parse::<u64>()
+                    .map_err(|_| invalid("invalid ack id"))?,
+            ),
+        };
+
+        let rest: String = chars.collect();
+        let data = match rest.is_empty() {
+            true => None,
+            false => Some(Value::parse(&rest)?),
+        };
+
+        Ok((
+            Packet {
+                kind,
+                nsp,
+                id,
+                data,
+            },
+            attachments,
+        ))
+    }
+
+    /// put attachments back in place of placeholders
+    ///
+    /// only binary packets carry placeholders, other packets are left as is
+    fn reconstruct(mut self, buffers: &[Vec<u8>]) -> io::Result<Self> {
+        if !matches!(self.kind, PacketKind::BinaryEvent | PacketKind::BinaryAck) {
+            return Ok(self);
+        }
+        self.data = self.data.map(|d| d.reconstruct(buffers)).transpose()?;
+        self.kind = match self.kind {
+            PacketKind::BinaryEvent => PacketKind::Event,
+            PacketKind::BinaryAck => PacketKind::Ack,
+            kind => kind,
+        };
+        Ok(self)
+    }
+}
+
+/// reassembles packets from text and following binary messages
+#[derive(Debug, Default)]
+pub struct Decoder {
+    /// binary packet waiting for attachments
+    pending: Option<(Packet, usize)>,
+    buffers: Vec<Vec<u8>>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// feed packet text, returns the packet once complete
+    pub fn add_text(&mut self, str: &str) -> io::Result<Option<Packet>> {
+        if self.pending.is_some() {
+            self.reset();
+            return Err(invalid("text packet while waiting for attachments"));
+        }
+        let (packet, attachments) = Packet::decode(str)?;
+        if attachments == 0 {
+            return packet.reconstruct(&[]).map(Some);
+        }
+        self.pending = Some((packet, attachments));
+        Ok(None)
+    }
+
+    /// feed binary attachment, returns the packet once complete
+    pub fn add_binary(&mut self, data: Vec<u8>) -> io::Result<Option<Packet>> {
+        let Some((_, expected)) = &self.pending else {
+            return Err(invalid("unexpected binary attachment"));
+        };
+        self.buffers.push(data);
+        if self.buffers.len() < *expected {
+            return Ok(None);
+        }
+
+        let (packet, _) = self.pending.take().unwrap();
+        let buffers = std::mem::take(&mut self.buffers);
+        packet.reconstruct(&buffers).map(Some)
+    }
+
+    /// drop partially received packet
+    pub fn reset(&mut self) {
+        self.pending = None;
+        self.buffers.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn json_format_and_parse() {
+        let text = r#"{"a":[1,-2.5,1e3,true,false,null],"b":"q\"\\\n\u00e9\ud83d\ude00","c":{}}"#;
+        let value = Value::parse(text).unwrap();
+        assert_eq!(
+            value,
+            Value::Object(vec![
+                (
+                    String::from("a"),
+                    Value::Array(vec![
+                        Value::Number(1.0),
+                        Value::Number(-2.5),
+                        Value::Number(1000.0),
+                        Value::Bool(true),
+                        Value::Bool(false),
+                        Value::Null,
+                    ])
+                ),
+                (String::from("b"), Value::from("q\"\\\né😀")),
+                (String::from("c"), Value::Object(vec![])),
+            ])
+        );
+        assert_eq!(Value::parse(&value.to_string()).unwrap(), value);
+        assert_eq!(Value::parse(" [ ] ").unwrap(), Value::Array(vec![]));
+
+        for bad in [
+            "", "[1,]", "{\"a\"}", "01", "1.", "+1", "\"\\x\"", "[1] 2", "nul",
+        ] {
+            assert!(Value::parse(bad).is_err(), "{bad}");
+        }
+        assert!(Value::parse(&"[".repeat(100)).is_err());
+    }
+
+    #[test]
+    fn packet_encode_and_decode() {
+        let mut packet = Packet::event("hello", vec![Value::from("world"), Value::from(1.0)]);
+        let (text, buffers) = packet.encode();
+        assert_eq!(text, "2[\"hello\",\"world\",1]");
+        assert!(buffers.is_empty());
+        assert_eq!(Packet::decode(&text).unwrap(), (packet.clone(), 0));
+
+        packet.nsp = String::from("/admin");
+        packet.id = Some(12);
+        let (text, _) = packet.encode();
+        assert_eq!(text, "2/admin,12[\"hello\",\"world\",1]");
+        assert_eq!(Packet::decode(&text).unwrap(), (packet, 0));
+
+        let (connect, _) = Packet::decode("0").unwrap();
+        assert_eq!(connect.kind, PacketKind::Connect);
+        assert_eq!(connect.data, None);
+
+        assert!(Packet::decode("9").is_err());
+        assert!(Packet::decode("51[]").is_err());
+        assert!(Packet::decode("599-[]").is_err());
+
+        // placeholder objects are plain json outside binary packets
+        let text = "2[\"plain\",{\"_placeholder\":true,\"num\":0}]";
+        let plain = Decoder::new().add_text(text).unwrap().unwrap();
+        assert_eq!(plain.encode().0, text);
+        assert!(Decoder::new()
+            .add_text("50-[\"x\",{\"_placeholder\":true,\"num\":0}]")
+            .is_err());
+    }
+
+    #[test]
+    fn binary_deconstruct_and_reconstruct() {
+        let packet = Packet::event(
+            "upload",
+            vec![
+                Value::Object(vec![
+                    (String::from("name"), Value::from("a.bin")),
+                    (String::from("file"), Value::from(vec![1u8, 2, 3])),
+                ]),
+                Value::from(vec![0xffu8]),
+            ],
+        );
+        let (text, buffers) = packet.encode();
+        assert_eq!(
+            text,
+            "52-[\"upload\",{\"name\":\"a.bin\",\"file\":{\"_placeholder\":true,\"num\":0}},{\"_placeholder\":true,\"num\":1}]"
+        );
+        assert_eq!(buffers, vec![vec![1, 2, 3], vec![0xff]]);
+
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.add_text(&text).unwrap(), None);
+        assert_eq!(decoder.add_binary(buffers[0].clone()).unwrap(), None);
+        let decoded = decoder.add_binary(buffers[1].clone()).unwrap().unwrap();
+        assert_eq!(decoded, packet);
+        assert_eq!(decoded.event_args().unwrap().0, "upload");
+
+        // attachments without packet, placeholder out of range
+        assert!(decoder.add_binary(vec![0]).is_err());
+        let bad = "51-[\"x\",{\"_placeholder\":true,\"num\":3}]";
+        assert_eq!(decoder.add_text(bad).unwrap(), None);
+        assert!(decoder.add_binary(vec![0]).is_err());
+        assert_eq!(
+            decoder
+                .add_text("2[\"ok\"]")
+                .unwrap()
+                .unwrap()
+                .event_args()
+                .unwrap()
+                .0,
+            "ok"
+        );
+    }
+}
@@ -1,105 +1,401 @@
 use std::{
     collections::{HashMap, HashSet},
-    io::{Read, Write},
-    net::{TcpListener, TcpStream},
-    sync::{Arc, Mutex},
+    fmt::{Debug, Display},
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
 };
 
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[cfg(unix)]
+use crate::net::bind_unix;
+
+use rand::{distributions::Alphanumeric, Rng};
+
+use super::{
+    adapter::{Adapter, BroadcastOptions, MemoryAdapter},
+    engine::{
+        decode_payload, encode_payload, open_packet, Packet, PacketType, Session, Transport,
+        PING_INTERVAL, PING_TIMEOUT,
+    },
+    packet::{self, Decoder, PacketKind, Value},
+};
+pub use crate::net::ShutdownHandle;
 use crate::{
-    websockets::server::{self, WebsocketConnection},
+    http::{header::RequestHeader, reader::HeadReader, response::Response, status::StatusCode},
+    net::{accept_failed, Listener, NetStream},
+    utils::base64::Base64,
+    websockets::{
+        message::Message,
+        server::{close_answer, violation, WebsocketConnection},
+    },
     worker::ThreadPool,
 };
 
-struct Event {
-    name: String,
-    id: Option<usize>,
-    room_id: Option<usize>,
-    payload: Option<String>,
+/// length of generated socket id
+const SID_LENGTH: usize = 20;
+/// path Engine.IO requests are served on
+const ENGINE_PATH: &str = "/socket.io/";
+/// path answering `200 ok` while the server accepts connections
+const HEALTH_PATH: &str = "/health";
+
+pub struct Event {
+    pub name: String,
+    /// socket id of the sender
+    pub id: Option<String>,
+    /// target room (every socket when `None`)
+    pub room_id: Option<String>,
+    /// event argument (several arguments are collected in an array)
+    pub payload: Option<Value>,
 }
 
 impl Event {
-    fn to_string(&self) -> String {
-        String::from(format!(
-            "event: {:?},\npayload: {:?}\n",
-            self.name, self.payload
-        ))
+    fn from_packet(id: &str, packet: &packet::Packet) -> Option<Self> {
+        let (name, args) = packet.event_args()?;
+        let payload = match args {
+            [] => None,
+            [arg] => Some(arg.clone()),
+            args => Some(Value::Array(args.to_vec())),
+        };
+        Some(Event {
+            name: String::from(name),
+            id: Some(String::from(id)),
+            room_id: None,
+            payload,
+        })
+    }
+
+    /// engine message carrying the event and its binary attachments
+    pub fn encode(&self) -> (String, Vec<Vec<u8>>) {
+        let args = self.payload.clone().into_iter().collect();
+        let (text, attachments) = packet::Packet::event(&self.name, args).encode();
+        (
+            Packet::new(PacketType::Message, &text).to_string(),
+            attachments,
+        )
+    }
+}
+
+impl Display for Event {
+    /// socket.io EVENT packet : `42["name",payload]`
+    /// (binary payload is written as placeholders)
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.encode().0)
     }
 }
 
 type Callback = fn(Event) -> Option<()>;
 
+/// socket id -> session
+type Connections<Stream> = Arc<Mutex<HashMap<String, Arc<Session<Stream>>>>>;
+
 #[derive(Debug)]
 pub struct Config {
-    url: String,
-    threads: usize,
-    max_payload_size: usize,
+    pub url: String,
+    /// connections served at once : each one holds a thread while it is
+    /// open (a long poll included), more are answered with 503
+    pub threads: usize,
+    /// largest message, and largest polling request body
+    pub max_payload_size: usize,
+    /// time between two pings sent to a session
+    pub ping_interval: Duration,
+    /// time a session has to answer a ping before it is closed
+    pub ping_timeout: Duration,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            url: String::from("127.0.0.1:8001"),
+            threads: 16,
+            max_payload_size: 1_000_000,
+            ping_interval: Duration::from_millis(PING_INTERVAL),
+            ping_timeout: Duration::from_millis(PING_TIMEOUT),
+        }
+    }
 }
 
-#[derive(Debug)]
 pub struct Server<Stream> {
-    connections: HashMap<String, Arc<Mutex<WebsocketConnection<Stream>>>>,
+    connections: Connections<Stream>,
     config: Config,
     listeners: HashMap<String, Callback>,
-    rooms: HashMap<usize, HashSet<usize>>,
+    /// keeps rooms and delivers broadcasts
+    adapter: Box<dyn Adapter>,
+    /// socket id -> packets waiting for binary attachments
+    decoders: HashMap<String, Decoder>,
+    /// namespaces clients may connect to
+    namespaces: HashSet<String>,
+    shutdown: ShutdownHandle,
+}
+
+impl<Stream> Debug for Server<Stream> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let connections: Vec<String> = self.connections.lock().unwrap().keys().cloned().collect();
+        f.debug_struct("Server")
+            .field("config", &self.config)
+            .field("connections", &connections)
+            .field("listeners", &self.listeners.keys())
+            .finish()
+    }
+}
+
+/// generate random socket id
+pub fn generate_sid() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SID_LENGTH)
+        .map(char::from)
+        .collect()
 }
 
-trait SocketIoServer<Stream> {
+pub trait SocketIoServer<Stream> {
     fn new(config: Config) -> Self;
     fn on(&mut self, event: String, callback: Callback);
-    fn enter(&mut self, id: usize, room_id: usize);
-    fn exit(&mut self, id: usize, room_id: usize);
+    /// register session and return its socket id
+    ///
+    /// `wc` : websocket writer, `None` for a polling session
+    fn register(&mut self, wc: Option<WebsocketConnection<Stream>>) -> String;
+    /// remove connection and leave every room it joined
+    fn disconnect(&mut self, id: &str);
+    fn enter(&mut self, id: &str, room: &str);
+    fn exit(&mut self, id: &str, room: &str);
     fn emit(&mut self, event: Event);
-    fn send(&mut self, id: usize, msg: String);
+    fn send(&mut self, id: &str, msg: String);
+    /// select sockets in `room`
+    fn to(&mut self, room: &str) -> BroadcastOperator<'_, Stream>;
+    /// alias of `to`
+    fn r#in(&mut self, room: &str) -> BroadcastOperator<'_, Stream>;
+    /// select every socket but the ones in `room`
+    fn except(&mut self, room: &str) -> BroadcastOperator<'_, Stream>;
+    /// select every socket but the sender
+    fn broadcast(&mut self, id: &str) -> BroadcastOperator<'_, Stream>;
     fn on_close(&mut self, callback: Callback);
     fn on_connect(&mut self, callback: Callback);
     // fn listen(&self);
 }
 
-impl<Stream> SocketIoServer<Stream> for Server<Stream>
+impl<Stream> Server<Stream>
 where
-    Stream: Unpin + Read + Write,
+    Stream: Unpin + Read + Write + Send + 'static,
 {
-    fn new(config: Config) -> Self {
+    /// create server sharing rooms and broadcasts through `adapter`
+    pub fn with_adapter(config: Config, mut adapter: Box<dyn Adapter>) -> Self {
+        let connections: Connections<Stream> = Arc::new(Mutex::new(HashMap::new()));
+
+        let targets = connections.clone();
+        adapter.init(Arc::new(move |id, packet, attachments| {
+            let target = targets.lock().unwrap().get(id).cloned();
+            if let Some(session) = target {
+                // the failed session is closed, and removed by the heartbeat
+                // or the thread reading its socket
+                if let Err(err) = session.send_with(String::from(packet), attachments) {
+                    println!("ERR>> {id}: {err}");
+                }
+            }
+        }));
+
         Self {
-            connections: HashMap::new(),
+            connections,
             config,
-            rooms: HashMap::new(),
             listeners: HashMap::new(),
+            adapter,
+            decoders: HashMap::new(),
+            namespaces: HashSet::from([String::from("/")]),
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+
+    /// accept connections to namespace `nsp` (`/` is always accepted)
+    pub fn of(&mut self, nsp: &str) {
+        self.namespaces.insert(String::from(nsp));
+    }
+
+    fn session(&self, id: &str) -> Option<Arc<Session<Stream>>> {
+        self.connections.lock().unwrap().get(id).cloned()
+    }
+
+    /// open packet of session `id`
+    fn open_packet(&self, id: &str, upgrades: &[&str]) -> Packet {
+        open_packet(
+            id,
+            upgrades,
+            self.config.max_payload_size,
+            self.config.ping_interval,
+            self.config.ping_timeout,
+        )
+    }
+
+    /// handle Engine.IO packet received from socket
+    fn on_packet(&mut self, id: &str, msg: &str) {
+        // binary message over polling
+        if let Some(encoded) = msg.strip_prefix('b') {
+            match Base64.decode(encoded) {
+                Ok(data) => self.on_binary(id, data),
+                Err(err) => println!("ERR>> invalid binary packet from {id}: {err}"),
+            }
+            return;
+        }
+        let Some(packet) = Packet::parse(msg) else {
+            println!("ERR>> invalid packet from {id}: {msg:?}");
+            return;
+        };
+        match packet.kind {
+            PacketType::Close => self.disconnect(id),
+            PacketType::Ping => self.send(id, Packet::new(PacketType::Pong, "").to_string()),
+            PacketType::Pong => {
+                if let Some(session) = self.session(id) {
+                    session.pong()
+                }
+            }
+            PacketType::Message => {
+                let decoded = self
+                    .decoders
+                    .entry(String::from(id))
+                    .or_default()
+                    .add_text(&packet.data);
+                self.on_decoded(id, decoded);
+            }
+            _ => {}
+        }
+    }
+
+    /// handle binary message (attachment of the pending packet)
+    fn on_binary(&mut self, id: &str, data: Vec<u8>) {
+        let decoded = self
+            .decoders
+            .entry(String::from(id))
+            .or_default()
+            .add_binary(data);
+        self.on_decoded(id, decoded);
+    }
+
+    fn on_decoded(&mut self, id: &str, decoded: io::Result<Option<packet::Packet>>) {
+        match decoded {
+            Ok(Some(packet)) => self.on_sio_packet(id, packet),
+            Ok(None) => {}
+            Err(err) => println!("ERR>> invalid packet from {id}: {err}"),
+        }
+    }
+
+    /// handle complete Socket.IO packet
+    fn on_sio_packet(&mut self, id: &str, packet: packet::Packet) {
+        match packet.kind {
+            PacketKind::Connect => {
+                let answer = if self.namespaces.contains(&packet.nsp) {
+                    packet::Packet {
+                        kind: PacketKind::Connect,
+                        nsp: packet.nsp,
+                        id: None,
+                        data: Some(Value::Object(vec![(String::from("sid"), Value::from(id))])),
+                    }
+                } else {
+                    println!("ERR>> {id} connects to unknown namespace {}", packet.nsp);
+                    packet::Packet {
+                        kind: PacketKind::ConnectError,
+                        nsp: packet.nsp,
+                        id: None,
+                        data: Some(Value::Object(vec![(
+                            String::from("message"),
+                            Value::from("Invalid namespace"),
+                        )])),
+                    }
+                };
+                let (text, _) = answer.encode();
+                self.send(id, Packet::new(PacketType::Message, &text).to_string());
+            }
+            PacketKind::Disconnect => self.disconnect(id),
+            PacketKind::Event => {
+                if !self.namespaces.contains(&packet.nsp) {
+                    println!("ERR>> {id} sent event to unknown namespace {}", packet.nsp);
+                    return;
+                }
+                let Some(event) = Event::from_packet(id, &packet) else {
+                    return;
+                };
+                if let Some(callback) = self.listeners.get(&event.name) {
+                    callback(event);
+                }
+            }
+            _ => {}
         }
     }
+}
+
+impl<Stream> SocketIoServer<Stream> for Server<Stream>
+where
+    Stream: Unpin + Read + Write + Send + 'static,
+{
+    fn new(config: Config) -> Self {
+        Self::with_adapter(config, Box::new(MemoryAdapter::new()))
+    }
     fn on(&mut self, event: String, callback: Callback) {
         self.listeners.insert(event, callback);
     }
-    fn enter(&mut self, id: usize, room_id: usize) {
-        self.rooms
-            .entry(room_id)
-            .and_modify(|r| {
-                r.insert(id);
-            })
-            .or_insert({
-                let mut new_set = HashSet::new();
-                new_set.insert(id);
-                new_set
-            });
+    fn register(&mut self, wc: Option<WebsocketConnection<Stream>>) -> String {
+        let mut connections = self.connections.lock().unwrap();
+        let mut id = generate_sid();
+        while connections.contains_key(&id) {
+            id = generate_sid();
+        }
+        let session = match wc {
+            Some(wc) => Session::websocket(&id, wc),
+            None => Session::polling(&id),
+        };
+        connections.insert(id.clone(), Arc::new(session));
+        drop(connections);
+
+        // every socket joins the room named by its own id
+        self.adapter.add_all(&id, &[&id]);
+        id
     }
-    fn exit(&mut self, id: usize, room_id: usize) {
-        self.rooms.entry(room_id).and_modify(|r| {
-            r.remove(&id);
-        });
+    fn disconnect(&mut self, id: &str) {
+        self.adapter.del_all(id);
+        self.decoders.remove(id);
+        if let Some(session) = self.connections.lock().unwrap().remove(id) {
+            session.close();
+        }
     }
-    fn send(&mut self, id: usize, msg: String) {
-        let tc = self.connections.get_mut(&id.to_string()).unwrap();
-        tc.lock().unwrap().send_msg(msg)
+    fn enter(&mut self, id: &str, room: &str) {
+        self.adapter.add_all(id, &[room]);
     }
-    fn emit(&mut self, event: Event) {
-        if event.room_id.is_some() {
-            if let Some(target_ids) = self.rooms.get_mut(&event.room_id.unwrap()) {
-                for target in target_ids.clone().into_iter() {
-                    self.send(target, event.to_string())
-                }
+    fn exit(&mut self, id: &str, room: &str) {
+        self.adapter.del(id, room);
+    }
+    fn send(&mut self, id: &str, msg: String) {
+        if let Some(session) = self.session(id) {
+            if let Err(err) = session.send(msg) {
+                println!("ERR>> {id}: {err}");
+                self.disconnect(id);
             }
         }
     }
+    fn emit(&mut self, event: Event) {
+        let mut opts = BroadcastOptions::default();
+        if let Some(room) = &event.room_id {
+            opts.rooms.insert(room.clone());
+        }
+        let (msg, attachments) = event.encode();
+        self.adapter.broadcast(&msg, &attachments, &opts);
+    }
+    fn to(&mut self, room: &str) -> BroadcastOperator<'_, Stream> {
+        BroadcastOperator::new(self).to(room)
+    }
+    fn r#in(&mut self, room: &str) -> BroadcastOperator<'_, Stream> {
+        self.to(room)
+    }
+    fn except(&mut self, room: &str) -> BroadcastOperator<'_, Stream> {
+        BroadcastOperator::new(self).except(room)
+    }
+    fn broadcast(&mut self, id: &str) -> BroadcastOperator<'_, Stream> {
+        self.except(id)
+    }
     fn on_close(&mut self, callback: Callback) {
         self.listeners.insert(String::from("close"), callback);
     }
@@ -108,56 +404,1111 @@ where
     }
 }
 
+/// selects target sockets by rooms
+pub struct BroadcastOperator<'a, Stream> {
+    server: &'a mut Server<Stream>,
+    opts: BroadcastOptions,
+}
+
+impl<'a, Stream> BroadcastOperator<'a, Stream> {
+    fn new(server: &'a mut Server<Stream>) -> Self {
+        BroadcastOperator {
+            server,
+            opts: BroadcastOptions::default(),
+        }
+    }
+    /// add target room
+    pub fn to(mut self, room: &str) -> Self {
+        self.opts.rooms.insert(String::from(room));
+        self
+    }
+    /// alias of `to`
+    pub fn r#in(self, room: &str) -> Self {
+        self.to(room)
+    }
+    /// exclude sockets in room
+    pub fn except(mut self, room: &str) -> Self {
+        self.opts.except.insert(String::from(room));
+        self
+    }
+    /// local socket ids selected by this operator
+    pub fn sockets(&self) -> HashSet<String> {
+        self.server.adapter.sockets(&self.opts)
+    }
+    /// socket ids selected by this operator on every server sharing the adapter
+    pub fn fetch_sockets(&self) -> HashSet<String> {
+        self.server.adapter.fetch_sockets(&self.opts)
+    }
+    /// send event to every selected socket (once per socket)
+    pub fn emit(self, name: &str, payload: Option<Value>) {
+        let (msg, attachments) = Event {
+            name: String::from(name),
+            id: None,
+            room_id: None,
+            payload,
+        }
+        .encode();
+
+        self.server
+            .adapter
+            .broadcast(&msg, &attachments, &self.opts);
+    }
+}
+
+/// read request head and body (`Content-Length` bytes,
+/// or bytes received after the head, e.g. first WebSocket frames)
+///
+/// a body larger than `max_size` is answered with 413,
+/// an invalid `Content-Length` with 400
+fn read_request<S: NetStream>(
+    stream: &mut S,
+    max_size: usize,
+) -> io::Result<(RequestHeader, Vec<u8>)> {
+    let reader = HeadReader::default();
+    stream.set_read_timeout(reader.timeout)?;
+    let (req, mut body) = reader.read_request(stream)?;
+
+    let length = match req.get("Content-Length") {
+        None => None,
+        Some(len) => match len.trim().parse::<usize>() {
+            Ok(length) if length > max_size => {
+                respond(stream, StatusCode::PAYLOAD_TOO_LARGE, "")?;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("request body of {length} bytes (max {max_size})"),
+                ));
+            }
+            Ok(length) => Some(length),
+            Err(_) => {
+                respond(stream, StatusCode::BAD_REQUEST, "")?;
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid Content-Length {len:?}"),
+                ));
+            }
+        },
+    };
+    if let Some(length) = length {
+        if length > body.len() {
+            let mut rest = vec![0u8; length - body.len()];
+            stream.read_exact(&mut rest)?;
+            body.extend(rest);
+        }
+        body.truncate(length);
+    }
+    stream.set_read_timeout(None)?;
+
+    Ok((req, body))
+}
+
+/// write plain text response and close
+fn respond<W: Write>(stream: &mut W, status: StatusCode, body: &str) -> io::Result<()> {
+    Response::text(status, body)
+        .header("Connection", "close")
+        .write_to(stream)
+}
+
+/// Engine.IO error response
+fn respond_error<W: Write>(stream: &mut W, code: u8, message: &str) -> io::Result<()> {
+    let body = format!("{{\"code\":{code},\"message\":{message:?}}}");
+    respond(stream, StatusCode::BAD_REQUEST, &body)
+}
+
 impl Server<TcpStream> {
     pub fn create(config: Config) -> Self {
-        let srv = Self::new(config);
-        srv
-    }
-    fn manage_connection(&mut self, stream: TcpStream) {
-        // TODO: manage ID
-        let id = stream.peer_addr().unwrap().to_string();
-        let wc = Arc::new(Mutex::new(WebsocketConnection::new(
-            stream.try_clone().expect("clone faild"),
-            Some(self.config.max_payload_size),
-        )));
-        println!("peer: {id}");
-        wc.lock().unwrap().handshake().unwrap();
-        // TODO: add socketio spec handshake
-        println!("sio handshake...");
-        let ans = String::from(
-"0{\"sid\":\"lv_VI97HAXpY6yYWAAAC\",\"upgrades\":[\"websocket\"],\"pingInterval\":25000,\"pingTimeout\":20000,\"maxPayload\":1000000}"
-        );
-        wc.lock().unwrap().send_msg(ans);
-        self.connections.insert(id, wc.clone());
-
-        // TODO: manage received event
+        Self::new(config)
+    }
+    /// bind `config.url` and serve connections until shutdown
+    pub fn listen(self) -> io::Result<()> {
+        let listener = TcpListener::bind(&self.config.url)?;
+        self.serve(listener)
+    }
+}
+
+#[cfg(unix)]
+impl Server<UnixStream> {
+    /// bind socket file `config.url` and serve connections until shutdown
+    pub fn listen(self) -> io::Result<()> {
+        let listener = bind_unix(&self.config.url)?;
+        self.serve(listener)
+    }
+}
+
+impl<Stream: NetStream> Server<Stream> {
+    /// handle used to stop `listen` / `serve` from another thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+    /// serve connections accepted on `listener` on a pool of `config.threads`
+    ///
+    /// returns once shutdown was requested and every connection is closed
+    pub fn serve<L: Listener<Stream = Stream>>(self, listener: L) -> io::Result<()> {
+        let handle = self.shutdown_handle();
+        handle.set_addr(listener.local_addr()?);
+
+        let size = self.config.threads;
+        let threads = ThreadPool::build(size);
+        let tick = self.config.ping_interval.min(self.config.ping_timeout) / 4;
+        let server = Arc::new(Mutex::new(self));
+
+        // pings sessions every tick until `stop` is dropped
+        let (stop, stopped) = mpsc::channel::<()>();
+        let heartbeat = {
+            let server = server.clone();
+            let tick = tick.max(Duration::from_millis(1));
+            thread::spawn(move || {
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(tick) {
+                    server.lock().unwrap().heartbeat();
+                }
+            })
+        };
+
+        while !handle.is_stopped() {
+            let mut stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(err) => {
+                    accept_failed(&err);
+                    continue;
+                }
+            };
+            if handle.is_stopped() {
+                break;
+            }
+            // no thread left : answer now rather than queue the connection
+            let stats = threads.stats();
+            if stats.active + stats.queued >= size {
+                println!("ERR>> every thread is busy, rejecting {}", stream.peer());
+                let _ = respond(&mut stream, StatusCode::SERVICE_UNAVAILABLE, "");
+                continue;
+            }
+            let server = server.clone();
+            threads.excute(move || Self::manage_connection(&server, stream));
+        }
+
+        println!("Shutting down socket.io server");
+        drop(stop);
+        let _ = heartbeat.join();
+        server.lock().unwrap().close_all();
+        // wait for running connections
+        drop(threads);
+        Ok(())
+    }
+
+    /// close every session and its socket
+    fn close_all(&mut self) {
+        let sessions: Vec<Arc<Session<Stream>>> =
+            self.connections.lock().unwrap().values().cloned().collect();
+        for session in sessions {
+            self.close_session(&session);
+        }
+    }
+
+    /// ping sessions and close the ones not answering in time
+    fn heartbeat(&mut self) {
+        let (interval, timeout) = (self.config.ping_interval, self.config.ping_timeout);
+        let sessions: Vec<Arc<Session<Stream>>> =
+            self.connections.lock().unwrap().values().cloned().collect();
+        for session in sessions {
+            if !session.heartbeat(interval, timeout) {
+                println!("ERR>> {} did not answer ping in time", session.sid);
+                self.close_session(&session);
+            }
+        }
+    }
+
+    /// disconnect session and close its socket
+    fn close_session(&mut self, session: &Session<Stream>) {
+        if let Some(wc) = &session.state.lock().unwrap().websocket {
+            // wakes up the thread reading this socket
+            let _ = wc.stream.shutdown(Shutdown::Both);
+        }
+        self.disconnect(&session.sid);
+    }
+
+    /// serve a single connection until it is closed
+    ///
+    /// polling requests are answered and closed,
+    /// websocket connections are served until the client closes.
+    /// `server` is only locked while handling packets, never while waiting
+    pub fn manage_connection(server: &Mutex<Self>, mut stream: Stream) {
+        let max_size = server.lock().unwrap().config.max_payload_size;
+        let (req, body) = match read_request(&mut stream, max_size) {
+            Ok(request) => request,
+            Err(err) => {
+                println!("ERR>> {err}");
+                return;
+            }
+        };
+
+        let result = if req.path() == HEALTH_PATH {
+            Self::health(server, &mut stream)
+        } else if !req.path().starts_with(ENGINE_PATH) {
+            respond(&mut stream, StatusCode::NOT_FOUND, "")
+        } else if req.query("EIO") != Some("4") {
+            respond_error(&mut stream, 5, "Unsupported protocol version")
+        } else {
+            let sid = req.query("sid").map(String::from);
+            match (req.query("transport"), req.method()) {
+                (Some("polling"), "GET") => match sid {
+                    None => Self::open_polling(server, &mut stream),
+                    Some(sid) => Self::poll(server, &mut stream, &sid),
+                },
+                (Some("polling"), "POST") => match sid {
+                    Some(sid) => Self::post(server, &mut stream, &sid, &body),
+                    None => respond_error(&mut stream, 1, "Session ID unknown"),
+                },
+                (Some("websocket"), "GET") => {
+                    Self::serve_websocket(server, stream, &req, &body, sid.as_deref())
+                }
+                (Some("polling"), _) => respond_error(&mut stream, 2, "Bad handshake method"),
+                _ => respond_error(&mut stream, 0, "Transport unknown"),
+            }
+        };
+
+        if let Err(err) = result {
+            println!("ERR>> {err}");
+        }
+    }
+
+    /// health check : 200 while serving, 503 once shutdown was requested
+    fn health(server: &Mutex<Self>, stream: &mut Stream) -> io::Result<()> {
+        if server.lock().unwrap().shutdown.is_stopped() {
+            return respond(stream, StatusCode::SERVICE_UNAVAILABLE, "");
+        }
+        respond(stream, StatusCode::OK, "ok")
+    }
+
+    /// polling handshake : create session and answer with open packet
+    fn open_polling(server: &Mutex<Self>, stream: &mut Stream) -> io::Result<()> {
+        let mut srv = server.lock().unwrap();
+        if srv.shutdown.is_stopped() {
+            return respond(stream, StatusCode::SERVICE_UNAVAILABLE, "");
+        }
+        let id = srv.register(None);
+        let open = srv.open_packet(&id, &["websocket"]);
+        drop(srv);
+
+        respond(stream, StatusCode::OK, &open.to_string())
+    }
+
+    /// long polling GET : answer with buffered packets
+    fn poll(server: &Mutex<Self>, stream: &mut Stream, id: &str) -> io::Result<()> {
+        let (session, timeout) = {
+            let srv = server.lock().unwrap();
+            match srv.session(id) {
+                Some(session) if session.transport() == Transport::Polling => {
+                    (session, srv.config.ping_interval)
+                }
+                _ => return respond_error(stream, 1, "Session ID unknown"),
+            }
+        };
+        let packets = session.poll(timeout);
+        respond(stream, StatusCode::OK, &encode_payload(&packets))
+    }
+
+    /// polling POST : handle every packet in payload
+    fn post(server: &Mutex<Self>, stream: &mut Stream, id: &str, body: &[u8]) -> io::Result<()> {
+        let mut srv = server.lock().unwrap();
+        if srv.session(id).is_none() {
+            drop(srv);
+            return respond_error(stream, 1, "Session ID unknown");
+        }
+        let Ok(payload) = std::str::from_utf8(body) else {
+            drop(srv);
+            return respond_error(stream, 3, "Bad request");
+        };
+        for packet in decode_payload(payload) {
+            srv.on_packet(id, packet);
+        }
+        drop(srv);
+        respond(stream, StatusCode::OK, "ok")
+    }
+
+    /// websocket connection : new session, or upgrade of polling session `sid`
+    fn serve_websocket(
+        server: &Mutex<Self>,
+        stream: Stream,
+        req: &RequestHeader,
+        received: &[u8],
+        sid: Option<&str>,
+    ) -> io::Result<()> {
+        let max_size = Some(server.lock().unwrap().config.max_payload_size);
+        let mut wc = WebsocketConnection::new(stream.try_clone()?, max_size);
+        wc.feed(received);
+        let writer = WebsocketConnection::new(stream, max_size);
+
+        let id = match sid {
+            None => {
+                let mut srv = server.lock().unwrap();
+                if srv.shutdown.is_stopped() {
+                    return respond(&mut wc.stream, StatusCode::SERVICE_UNAVAILABLE, "");
+                }
+                wc.accept(req)?;
+                let id = srv.register(Some(writer));
+                let open = srv.open_packet(&id, &[]);
+                srv.send(&id, open.to_string());
+                id
+            }
+            Some(sid) => {
+                let session = server.lock().unwrap().session(sid);
+                let session = match session {
+                    Some(session) if session.transport() == Transport::Polling => session,
+                    _ => return respond_error(&mut wc.stream, 1, "Session ID unknown"),
+                };
+                wc.accept(req)?;
+                if !Self::upgrade(&mut wc, &session) {
+                    println!("ERR>> upgrade of {sid} failed");
+                    return Ok(());
+                }
+                if let Err(err) = session.upgrade(writer) {
+                    server.lock().unwrap().disconnect(sid);
+                    return Err(err);
+                }
+                String::from(sid)
+            }
+        };
+
+        let Some(session) = server.lock().unwrap().session(&id) else {
+            return Ok(());
+        };
+        // fragments are reassembled; pongs and close answers share the
+        // session writer with packets
         loop {
-            let packet = wc.lock().unwrap().receive();
-            let msg = String::from_utf8(packet.payload.clone()).unwrap();
-            if msg == "40" {
-                let ans = String::from("40{\"sid\":\"lv_VI97HAXpY6yYWAAAC\"}");
-                wc.lock().unwrap().send_msg(ans);
-            } else if msg != "3" {
-                if msg == "42[\"test\",\"\"]" {
-                    println!("{:?}", self);
-                    let msg = String::from("42[\"testing\",\"hello socket.io\"]");
-                    wc.lock().unwrap().send_msg(msg);
-                } else {
-                    let ping = String::from("2");
-                    wc.lock().unwrap().send_msg(ping);
+            let message = wc.receive_message();
+            let mut srv = server.lock().unwrap();
+            match message {
+                Ok(Message::Text(msg)) => srv.on_packet(&id, &msg),
+                Ok(Message::Binary(data)) => srv.on_binary(&id, data),
+                Ok(Message::Ping(payload)) => {
+                    let _ = session.send_message(Message::Pong(payload));
                 }
+                Ok(Message::Pong(_)) => {}
+                Ok(Message::Close(close)) => {
+                    let _ = session.send_message(Message::Close(close_answer(&close)));
+                    break;
+                }
+                Err(err) => {
+                    if let Some(close) = violation(&err) {
+                        let _ = session.send_message(Message::Close(Some(close.clone())));
+                    }
+                    break;
+                }
+            }
+            if srv.session(&id).is_none() {
+                break;
             }
         }
+
+        server.lock().unwrap().disconnect(&id);
+        Ok(())
+    }
+
+    /// probe (ping/pong) and wait for upgrade packet
+    fn upgrade(wc: &mut WebsocketConnection<Stream>, session: &Session<Stream>) -> bool {
+        let probe = Packet::new(PacketType::Ping, "probe").to_string();
+        match wc.receive_message() {
+            Ok(Message::Text(text)) if text == probe => {}
+            _ => return false,
+        }
+        if wc
+            .send_msg(Packet::new(PacketType::Pong, "probe").to_string())
+            .is_err()
+        {
+            return false;
+        }
+
+        // let pending poll return so that the client can pause polling
+        if session
+            .send(Packet::new(PacketType::Noop, "").to_string())
+            .is_err()
+        {
+            return false;
+        }
+
+        let upgrade = Packet::new(PacketType::Upgrade, "").to_string();
+        matches!(wc.receive_message(), Ok(Message::Text(text)) if text == upgrade)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufRead, BufReader, Cursor},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use crate::net::ACCEPT_BACKOFF;
+
+    use super::*;
+
+    fn create() -> Server<Cursor<Vec<u8>>> {
+        Server::new(Config {
+            url: String::from("127.0.0.1:0"),
+            threads: 1,
+            max_payload_size: 1024,
+            ..Config::default()
+        })
+    }
+
+    fn connect(srv: &mut Server<Cursor<Vec<u8>>>) -> String {
+        srv.register(Some(WebsocketConnection::new(
+            Cursor::new(Vec::new()),
+            None,
+        )))
+    }
+
+    fn received(srv: &Server<Cursor<Vec<u8>>>, id: &str) -> usize {
+        let session = srv.session(id).unwrap();
+        let state = session.state.lock().unwrap();
+        let written = state.websocket.as_ref().unwrap().stream.get_ref();
+        written.windows(4).filter(|w| w == b"42[\"").count()
+    }
+
+    #[test]
+    fn rooms_keyed_by_socket_id() {
+        let mut srv = create();
+        let a = connect(&mut srv);
+        let b = connect(&mut srv);
+
+        srv.enter(&a, "lobby");
+        srv.enter(&b, "lobby");
+        assert_eq!(srv.to("lobby").sockets().len(), 2);
+        assert_eq!(srv.to(&a).sockets(), HashSet::from([a.clone()]));
+
+        srv.send(&a, Event::to_string(&test_event()));
+        assert_eq!(received(&srv, &a), 1);
+
+        srv.exit(&a, "lobby");
+        srv.exit(&b, "lobby");
+        assert!(srv.to("lobby").sockets().is_empty());
+    }
+
+    #[test]
+    fn broadcast_operators() {
+        let mut srv = create();
+        let a = connect(&mut srv);
+        let b = connect(&mut srv);
+        let c = connect(&mut srv);
+
+        srv.enter(&a, "red");
+        srv.enter(&b, "red");
+        srv.enter(&b, "blue");
+        srv.enter(&c, "blue");
+
+        // union of rooms, each socket once
+        srv.to("red").r#in("blue").emit("union", None);
+        assert_eq!(
+            (received(&srv, &a), received(&srv, &b), received(&srv, &c)),
+            (1, 1, 1)
+        );
+
+        srv.to("red").except("blue").emit("red-only", None);
+        assert_eq!(
+            (received(&srv, &a), received(&srv, &b), received(&srv, &c)),
+            (2, 1, 1)
+        );
+
+        srv.broadcast(&a).emit("others", Some(Value::from(1.0)));
+        assert_eq!(
+            (received(&srv, &a), received(&srv, &b), received(&srv, &c)),
+            (2, 2, 2)
+        );
+
+        srv.emit(Event {
+            room_id: Some(String::from("blue")),
+            ..test_event()
+        });
+        assert_eq!(
+            (received(&srv, &a), received(&srv, &b), received(&srv, &c)),
+            (2, 3, 3)
+        );
+    }
+
+    /// events received with a binary payload
+    static UPLOADS: AtomicUsize = AtomicUsize::new(0);
+
+    fn count_upload(event: Event) -> Option<()> {
+        if let Some(Value::Binary(_)) = event.payload {
+            UPLOADS.fetch_add(1, Ordering::SeqCst);
+        }
+        Some(())
+    }
+
+    #[test]
+    fn binary_attachments() {
+        let mut srv = create();
+        let a = connect(&mut srv);
+        srv.on(String::from("upload"), count_upload);
+
+        srv.to(&a).emit("file", Some(Value::from(vec![1, 2, 3])));
+        {
+            let session = srv.session(&a).unwrap();
+            let state = session.state.lock().unwrap();
+            let written = state.websocket.as_ref().unwrap().stream.get_ref();
+            let text = b"451-[\"file\",{\"_placeholder\":true,\"num\":0}]";
+            assert_eq!(&written[2..2 + text.len()], text);
+            assert!(written.ends_with(&[0x82, 3, 1, 2, 3]));
+        }
+
+        // event is dispatched once its attachment arrived
+        srv.on_packet(&a, "451-[\"upload\",{\"_placeholder\":true,\"num\":0}]");
+        assert_eq!(UPLOADS.load(Ordering::SeqCst), 0);
+        srv.on_binary(&a, vec![9]);
+        assert_eq!(UPLOADS.load(Ordering::SeqCst), 1);
+
+        // attachment sent over polling as base64
+        srv.on_packet(&a, "451-[\"upload\",{\"_placeholder\":true,\"num\":0}]");
+        srv.on_packet(&a, "bCQ==");
+        assert_eq!(UPLOADS.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn connect_to_namespaces() {
+        let mut srv = create();
+        let a = connect(&mut srv);
+        let written = |srv: &Server<Cursor<Vec<u8>>>| {
+            let session = srv.session(&a).unwrap();
+            let state = session.state.lock().unwrap();
+            let written = state.websocket.as_ref().unwrap().stream.get_ref();
+            String::from_utf8_lossy(written).into_owned()
+        };
+
+        srv.on_packet(&a, "40/admin,");
+        assert!(written(&srv).ends_with("44/admin,{\"message\":\"Invalid namespace\"}"));
+
+        srv.of("/admin");
+        srv.on_packet(&a, "40/admin,");
+        assert!(written(&srv).ends_with(&format!("40/admin,{{\"sid\":\"{a}\"}}")));
+        srv.on_packet(&a, "40");
+        assert!(written(&srv).ends_with(&format!("40{{\"sid\":\"{a}\"}}")));
+    }
+
+    #[test]
+    fn leave_rooms_on_disconnect() {
+        let mut srv = create();
+        let a = connect(&mut srv);
+        let b = connect(&mut srv);
+        srv.enter(&a, "solo");
+        srv.enter(&a, "pair");
+        srv.enter(&b, "pair");
+
+        srv.disconnect(&a);
+
+        assert!(!srv.connections.lock().unwrap().contains_key(&a));
+        assert!(srv.adapter.socket_rooms(&a).is_empty());
+        assert!(srv.to("solo").sockets().is_empty());
+        assert!(srv.to(&a).sockets().is_empty());
+        assert_eq!(srv.to("pair").sockets(), HashSet::from([b]));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn broadcast_between_servers() {
+        use crate::socketio::ipc::{IpcAdapter, IpcHub};
+        use std::{
+            thread,
+            time::{Duration, Instant},
+        };
+
+        let wait_until = |cond: &dyn Fn() -> bool| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while Instant::now() < deadline {
+                if cond() {
+                    return true;
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            false
+        };
+
+        let path = std::env::temp_dir().join(format!("sockets-ipc-{}.sock", std::process::id()));
+        let hub = IpcHub::bind(&path).unwrap();
+        let create = || {
+            Server::with_adapter(
+                Config {
+                    url: String::from("127.0.0.1:0"),
+                    threads: 1,
+                    max_payload_size: 1024,
+                    ..Config::default()
+                },
+                Box::new(IpcAdapter::connect(&path).unwrap()),
+            )
+        };
+        let mut srv1: Server<Cursor<Vec<u8>>> = create();
+        let mut srv2: Server<Cursor<Vec<u8>>> = create();
+        assert!(wait_until(&|| hub.peers() == 2));
+
+        let a = connect(&mut srv1);
+        let b = connect(&mut srv2);
+        let c = connect(&mut srv2);
+        srv1.enter(&a, "room");
+        srv2.enter(&b, "room");
+
+        assert_eq!(
+            srv1.to("room").fetch_sockets(),
+            HashSet::from([a.clone(), b.clone()])
+        );
+        assert_eq!(srv2.to("room").sockets(), HashSet::from([b.clone()]));
+
+        srv1.broadcast(&a).emit("hello", Some(Value::from("world")));
+        assert!(wait_until(
+            &|| received(&srv2, &b) == 1 && received(&srv2, &c) == 1
+        ));
+        assert_eq!(received(&srv1, &a), 0);
+
+        srv2.to("room").emit("room", None);
+        assert!(wait_until(&|| received(&srv1, &a) == 1));
+        assert_eq!(received(&srv2, &b), 2);
+
+        drop(hub);
+        assert!(!path.exists());
+    }
+
+    /// send raw request to `srv` and return the raw response
+    fn request(srv: &Mutex<Server<TcpStream>>, listener: &TcpListener, raw: &str) -> String {
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(raw.as_bytes()).unwrap();
+        Server::manage_connection(srv, listener.accept().unwrap().0);
+
+        let mut res = String::new();
+        client.read_to_string(&mut res).unwrap();
+        res
+    }
+
+    fn body(res: &str) -> &str {
+        res.split_once("\r\n\r\n").unwrap().1
+    }
+
+    fn sid(open: &str) -> String {
+        let start = open.find("\"sid\":\"").unwrap() + 7;
+        String::from(&open[start..start + SID_LENGTH])
     }
 
-    pub fn listen(&'static mut self) {
-        let listener = TcpListener::bind(self.config.url.clone()).unwrap();
-        let threads = ThreadPool::build(self.config.threads);
+    #[test]
+    fn polling_transport() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let srv = &Mutex::new(Server::create(Config {
+            url: String::from("127.0.0.1:0"),
+            threads: 1,
+            max_payload_size: 1024,
+            ..Config::default()
+        }));
 
-        // for stream in listener.incoming() {
-        //     let stream = stream.unwrap();
-        //     threads.excute(|| self.manage_connection(stream))
-        // }
-        unimplemented!()
+        let res = request(
+            srv,
+            &listener,
+            "GET /socket.io/?EIO=4&transport=polling HTTP/1.1\r\nHost: localhost\r\n\r\n",
+        );
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(body(&res).starts_with("0{\"sid\":"));
+        assert!(body(&res).contains("\"upgrades\":[\"websocket\"]"));
+        let id = sid(body(&res));
+
+        // connect to default namespace and to an unknown one
+        let payload = "40\x1e40/admin,";
+        let res = request(
+            srv,
+            &listener,
+            &format!(
+                "POST /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{payload}",
+                payload.len()
+            ),
+        );
+        assert_eq!(body(&res), "ok");
+
+        let res = request(
+            srv,
+            &listener,
+            &format!("GET /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\n\r\n"),
+        );
+        assert_eq!(
+            body(&res),
+            format!("40{{\"sid\":\"{id}\"}}\x1e44/admin,{{\"message\":\"Invalid namespace\"}}")
+        );
+
+        let res = request(
+            srv,
+            &listener,
+            "GET /socket.io/?EIO=4&transport=polling&sid=unknown HTTP/1.1\r\n\r\n",
+        );
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert_eq!(
+            body(&res),
+            "{\"code\":1,\"message\":\"Session ID unknown\"}"
+        );
+
+        let res = request(
+            srv,
+            &listener,
+            "GET /socket.io/?EIO=3&transport=polling HTTP/1.1\r\n\r\n",
+        );
+        assert!(body(&res).contains("\"code\":5"));
+
+        let res = request(srv, &listener, "GET /health HTTP/1.1\r\n\r\n");
+        assert!(res.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert_eq!(body(&res), "ok");
+
+        let res = request(srv, &listener, "GET /unknown HTTP/1.1\r\n\r\n");
+        assert!(res.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+
+    #[test]
+    fn polling_upgrade_to_websocket() {
+        use crate::websockets::frame::{Control, Data, Frame, FrameHeader, Opcode};
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let srv = Mutex::new(Server::create(Config {
+                url: String::from("127.0.0.1:0"),
+                threads: 1,
+                max_payload_size: 1024,
+                ..Config::default()
+            }));
+            // handshake, pending poll and websocket
+            thread::scope(|scope| {
+                for _ in 0..3 {
+                    let stream = listener.accept().unwrap().0;
+                    let srv = &srv;
+                    scope.spawn(move || Server::manage_connection(srv, stream));
+                }
+            });
+            let remain = srv.lock().unwrap().connections.lock().unwrap().len();
+            remain
+        });
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET /socket.io/?EIO=4&transport=polling HTTP/1.1\r\n\r\n")
+            .unwrap();
+        let mut res = String::new();
+        client.read_to_string(&mut res).unwrap();
+        let id = sid(body(&res));
+
+        let poll = format!("GET /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\n\r\n");
+        let poller = thread::spawn(move || {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(poll.as_bytes()).unwrap();
+            let mut res = String::new();
+            client.read_to_string(&mut res).unwrap();
+            res
+        });
+
+        let mut ws = TcpStream::connect(addr).unwrap();
+        let upgrade = format!(
+            "GET /socket.io/?EIO=4&transport=websocket&sid={id} HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        );
+        ws.write_all(upgrade.as_bytes()).unwrap();
+        let mut reader = BufReader::new(ws.try_clone().unwrap());
+        let mut line = String::new();
+        while line != "\r\n" {
+            line.clear();
+            reader.read_line(&mut line).unwrap();
+        }
+
+        let mut send_frame = |fin, opcode, msg: &str| {
+            // masked with zero key, payload stays as is
+            let frame = Frame {
+                header: FrameHeader {
+                    fin,
+                    rsv1: false,
+                    rsv2: false,
+                    rsv3: false,
+                    opcode,
+                    masked: true,
+                    payloadlength: msg.len() as u64,
+                    mask: Some(0),
+                },
+                payload: Vec::from(msg.as_bytes()),
+            };
+            // single write : receive expects the whole frame in one read
+            let mut raw = Vec::new();
+            frame.format(&mut raw).unwrap();
+            ws.write_all(&raw).unwrap();
+        };
+        // first byte of the frame (fin and opcode) and payload
+        let mut recv_frame = || {
+            let mut head = [0u8; 2];
+            reader.read_exact(&mut head).unwrap();
+            let mut payload = vec![0u8; (head[1] & 0x7f) as usize];
+            reader.read_exact(&mut payload).unwrap();
+            (head[0], String::from_utf8(payload).unwrap())
+        };
+
+        let text = Opcode::Data(Data::Text);
+        send_frame(true, text, "2probe");
+        assert_eq!(recv_frame().1, "3probe");
+        // pending poll is ended with a noop
+        assert_eq!(body(&poller.join().unwrap()), "6");
+        send_frame(true, text, "5");
+        // noop is not sent through websocket
+        send_frame(true, text, "40");
+        assert_eq!(recv_frame().1, format!("40{{\"sid\":\"{id}\"}}"));
+
+        // fragments are handled as a single packet
+        send_frame(false, text, "40/ad");
+        send_frame(true, Opcode::Data(Data::Continue), "min,");
+        let refused = r#"44/admin,{"message":"Invalid namespace"}"#;
+        assert_eq!(recv_frame(), (0x81, String::from(refused)));
+        // ping is answered, close is echoed
+        send_frame(true, Opcode::Control(Control::Ping), "hi");
+        assert_eq!(recv_frame(), (0x8a, String::from("hi")));
+        send_frame(true, Opcode::Control(Control::Close), "");
+        assert_eq!(recv_frame(), (0x88, String::new()));
+
+        assert_eq!(server.join().unwrap(), 0);
+    }
+
+    #[test]
+    fn serve_concurrently_until_shutdown() {
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let srv = Server::create(Config {
+            url: String::from("127.0.0.1:0"),
+            threads: 4,
+            max_payload_size: 1024,
+            ..Config::default()
+        });
+        let handle = srv.shutdown_handle();
+        let server = thread::spawn(move || srv.serve(listener));
+
+        let send = move |raw: String| {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(raw.as_bytes()).unwrap();
+            let mut res = String::new();
+            client.read_to_string(&mut res).unwrap();
+            res
+        };
+
+        let res = send(String::from(
+            "GET /socket.io/?EIO=4&transport=polling HTTP/1.1\r\n\r\n",
+        ));
+        let id = sid(body(&res));
+
+        // pending long poll does not block other connections
+        let poll = format!("GET /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\n\r\n");
+        let poller = thread::spawn(move || send(poll));
+        let payload = "40";
+        let res = send(format!(
+            "POST /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\nContent-Length: {}\r\n\r\n{payload}",
+            payload.len()
+        ));
+        assert_eq!(body(&res), "ok");
+        assert_eq!(
+            body(&poller.join().unwrap()),
+            format!("40{{\"sid\":\"{id}\"}}")
+        );
+
+        // websocket session, closed by shutdown
+        let mut ws = TcpStream::connect(addr).unwrap();
+        ws.write_all(b"GET /socket.io/?EIO=4&transport=websocket HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").unwrap();
+        let mut received = Vec::new();
+        while !received.ends_with(b"\"maxPayload\":1024}") {
+            let mut buf = [0u8; 512];
+            let len = ws.read(&mut buf).unwrap();
+            assert!(len > 0);
+            received.extend_from_slice(&buf[..len]);
+        }
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+        assert_eq!(ws.read(&mut [0u8; 16]).unwrap(), 0);
+    }
+
+    #[test]
+    fn reject_oversized_and_invalid_bodies() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let srv = &Mutex::new(Server::create(Config {
+            url: String::from("127.0.0.1:0"),
+            threads: 1,
+            max_payload_size: 1024,
+            ..Config::default()
+        }));
+        let open = request(
+            srv,
+            &listener,
+            "GET /socket.io/?EIO=4&transport=polling HTTP/1.1\r\n\r\n",
+        );
+        let id = sid(body(&open));
+
+        // answered before any allocation or read of the body
+        let res = request(
+            srv,
+            &listener,
+            &format!("POST /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\nContent-Length: 99999999999\r\n\r\n"),
+        );
+        assert!(res.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+
+        let res = request(
+            srv,
+            &listener,
+            &format!("POST /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\nContent-Length: 1x\r\n\r\n40"),
+        );
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+
+    #[test]
+    fn close_sessions_not_answering_pings() {
+        use std::{thread, time::Instant};
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let srv = Server::create(Config {
+            url: String::from("127.0.0.1:0"),
+            threads: 4,
+            max_payload_size: 1024,
+            ping_interval: Duration::from_millis(50),
+            ping_timeout: Duration::from_millis(100),
+        });
+        let handle = srv.shutdown_handle();
+        let server = thread::spawn(move || srv.serve(listener));
+
+        let send = move |raw: String| {
+            let mut client = TcpStream::connect(addr).unwrap();
+            client.write_all(raw.as_bytes()).unwrap();
+            let mut res = String::new();
+            client.read_to_string(&mut res).unwrap();
+            res
+        };
+        let open = || {
+            sid(body(&send(String::from(
+                "GET /socket.io/?EIO=4&transport=polling HTTP/1.1\r\n\r\n",
+            ))))
+        };
+        let poll = |id: &str| {
+            send(format!(
+                "GET /socket.io/?EIO=4&transport=polling&sid={id} HTTP/1.1\r\n\r\n"
+            ))
+        };
+        let (silent, alive) = (open(), open());
+
+        // pinged after the interval, not answered
+        let start = Instant::now();
+        assert_eq!(body(&poll(&silent)), "2");
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // answers every ping for longer than interval + timeout
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(400) {
+            assert_eq!(body(&poll(&alive)), "2");
+            let pong = send(format!(
+                "POST /socket.io/?EIO=4&transport=polling&sid={alive} HTTP/1.1\r\nContent-Length: 1\r\n\r\n3"
+            ));
+            assert_eq!(body(&pong), "ok");
+        }
+
+        assert!(body(&poll(&silent)).contains("Session ID unknown"));
+        assert_eq!(body(&poll(&alive)), "2");
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn drop_sessions_of_gone_clients() {
+        use crate::net::pipe::{duplex, Pipe};
+
+        let mut srv: Server<Pipe> = Server::new(Config::default());
+        let mut open = || {
+            let (server, client) = duplex();
+            let id = srv.register(Some(WebsocketConnection::new(server, None)));
+            (id, client)
+        };
+        let (gone, client) = open();
+        let (left, left_client) = open();
+        let (alive, _client) = open();
+        drop(client);
+        drop(left_client);
+        let srv = Mutex::new(srv);
+
+        // broadcast : the session failing is closed, the others are served
+        srv.lock().unwrap().emit(test_event());
+        let mut server = srv.lock().unwrap();
+        assert!(server.session(&gone).unwrap().is_closed());
+        assert!(!server.session(&alive).unwrap().is_closed());
+        server.heartbeat();
+        assert!(server.session(&gone).is_none());
+        assert!(server.session(&alive).is_some());
+
+        // direct send : disconnected right away
+        server.send(&left, String::from("42[\"x\"]"));
+        assert!(server.session(&left).is_none());
+        drop(server);
+        assert!(!srv.is_poisoned());
+    }
+
+    #[test]
+    fn reject_connections_when_every_thread_is_busy() {
+        use std::thread;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let srv = Server::create(Config {
+            url: String::from("127.0.0.1:0"),
+            threads: 1,
+            max_payload_size: 1024,
+            ..Config::default()
+        });
+        let handle = srv.shutdown_handle();
+        let server = thread::spawn(move || srv.serve(listener));
+
+        // websocket session holds the only thread
+        let mut ws = TcpStream::connect(addr).unwrap();
+        ws.write_all(b"GET /socket.io/?EIO=4&transport=websocket HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n").unwrap();
+        let mut received = Vec::new();
+        while !received.ends_with(b"\"maxPayload\":1024}") {
+            let mut buf = [0u8; 512];
+            let len = ws.read(&mut buf).unwrap();
+            assert!(len > 0);
+            received.extend_from_slice(&buf[..len]);
+        }
+
+        let mut res = String::new();
+        TcpStream::connect(addr)
+            .unwrap()
+            .read_to_string(&mut res)
+            .unwrap();
+        assert!(res.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn back_off_after_accept_errors() {
+        use crate::net::Exhausted;
+        use std::{
+            thread,
+            time::{Duration, Instant},
+        };
+
+        let listener = Exhausted::bind();
+        let accepts = listener.accepts.clone();
+        let srv = Server::create(Config {
+            threads: 1,
+            ..Config::default()
+        });
+        let handle = srv.shutdown_handle();
+        let start = Instant::now();
+        let server = thread::spawn(move || srv.serve(listener));
+
+        thread::sleep(Duration::from_millis(300));
+        handle.shutdown();
+        server.join().unwrap().unwrap();
+        let most = start.elapsed().as_millis() / ACCEPT_BACKOFF.as_millis() + 1;
+        assert!(accepts.load(Ordering::SeqCst) as u128 <= most);
+    }
+
+    fn test_event() -> Event {
+        Event {
+            name: String::from("test"),
+            id: None,
+            room_id: None,
+            payload: Some(Value::from("hello")),
+        }
     }
 }
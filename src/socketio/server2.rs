@@ -1,135 +1,260 @@
 use std::{
-    net::{TcpListener, TcpStream},
+    io,
+    net::{Shutdown, TcpListener},
     sync::{
-        mpsc::{self, Receiver, Sender},
+        atomic::{AtomicIsize, Ordering},
+        mpsc::{self, Receiver, Sender, SyncSender, TrySendError},
         Arc, Mutex,
     },
     thread,
-    time::Duration,
 };
 
-use crate::websockets::server::WebsocketConnection;
-
-use super::core::{Army, Unit};
+use crate::{
+    http::{response::Response, status::StatusCode},
+    net::{accept_failed, Listener, NetStream},
+    websockets::{
+        frame::{Control, Data, Frame, Opcode},
+        server::WebsocketConnection,
+    },
+};
 
-static CLOCK: u64 = 10;
+use super::core::{Army, Commander, ConnectionId, Signal, Unit};
 
-struct Event {
-    name: String,
-    id: Option<usize>,
-    room_id: Option<usize>,
-    payload: Option<String>,
-}
-
-impl Event {
-    fn to_string(&self) -> String {
-        String::from(format!(
-            "event: {:?},\npayload: {:?}\n",
-            self.name, self.payload
-        ))
-    }
-}
+#[cfg(unix)]
+use crate::net::bind_unix;
 
 #[derive(Debug)]
 pub struct Config {
-    url: String,
-    max_connection: usize,
-    max_payload_size: usize,
+    pub url: String,
+    /// number of connection slots (threads serving a client)
+    pub max_connection: usize,
+    /// accepted connections waiting for a free slot
+    /// (rejected with 503 beyond that)
+    pub backlog: usize,
+    pub max_payload_size: usize,
 }
 
-pub struct Signal {
-    something: String,
+/// accepted stream waiting for a slot, whatever its transport
+pub trait Accepted: Send {
+    /// serve client until it leaves, frames are bounded by `max_size`
+    fn serve(self: Box<Self>, unit: &Unit, max_size: usize);
+    /// answer 503 and close
+    fn reject(self: Box<Self>);
 }
 
+impl<S: NetStream> Accepted for S {
+    fn serve(self: Box<Self>, unit: &Unit, max_size: usize) {
+        Connection::handle(unit, *self, max_size)
+    }
+    fn reject(self: Box<Self>) {
+        let mut stream = *self;
+        if let Err(err) = Response::error(StatusCode::SERVICE_UNAVAILABLE).write_to(&mut stream) {
+            println!("ERR>> {err}");
+        }
+    }
+}
+
+/// connection slot : thread serving clients one after the other
 pub struct Connection {
-    thd: thread::JoinHandle<()>,
+    pub thd: thread::JoinHandle<()>,
 }
 
 impl Connection {
+    /// spawn slot serving `first`, then every stream received from `job_rx`
+    ///
+    /// the slot adds one to `idle` each time it is about to wait on
+    /// `job_rx`, the pool takes it back when it hands a stream over
     pub fn build(
-        job_rx: Arc<Mutex<Receiver<TcpStream>>>,
-        mut sig_tx: Option<Sender<Box<dyn Send>>>,
-        mut sig_rx: Option<Receiver<Box<dyn Send>>>,
+        job_rx: Arc<Mutex<Receiver<Box<dyn Accepted>>>>,
+        idle: Arc<AtomicIsize>,
+        max_size: usize,
+        unit: Unit,
+        first: Box<dyn Accepted>,
     ) -> Connection {
-        let thread = thread::spawn(move || loop {
-            let sig = job_rx.lock().unwrap().recv();
-            match sig {
-                Ok(stream) => {
-                    self::Connection::handle(
-                        sig_tx.take().unwrap(),
-                        sig_rx.take().unwrap(),
-                        stream,
-                    );
-                }
-                Err(detail) => {
-                    println!("ERR>> {detail}");
-                    break;
+        let thread = thread::spawn(move || {
+            first.serve(&unit, max_size);
+            loop {
+                idle.fetch_add(1, Ordering::SeqCst);
+                let sig = job_rx.lock().unwrap().recv();
+                match sig {
+                    Ok(stream) => stream.serve(&unit, max_size),
+                    Err(detail) => {
+                        println!("ERR>> {detail}");
+                        break;
+                    }
                 }
             }
         });
 
         Connection { thd: thread }
     }
-    pub fn handle(
-        sig_tx: Sender<Box<dyn Send>>,
-        sig_rx: Receiver<Box<dyn Send>>,
-        stream: TcpStream,
-    ) {
-        let mut wc = WebsocketConnection::new(stream, None);
-        wc.handshake();
+    /// serve client : text messages are broadcast to every other connection,
+    /// messages delivered by the commander are written to the client
+    ///
+    /// a reader thread forwards client frames to the commander while this
+    /// thread blocks on the unit inbox, so nothing is polled : the client
+    /// is served until the commander acknowledges its `Disconnect`
+    ///
+    /// the slot only takes a new stream once that acknowledgement is read,
+    /// and the commander routes nothing to the slot until the next client
+    /// connects : no signal meant for this client reaches the next one
+    pub fn handle<S: NetStream>(unit: &Unit, stream: S, max_size: usize) {
+        // registered before the handshake answer, so that the client
+        // receives every broadcast sent once it is connected
+        let _ = unit.connect();
+        let mut wc = WebsocketConnection::new(stream, Some(max_size));
+        let reader = match wc.handshake().and_then(|_| wc.stream.try_clone()) {
+            Ok(stream) => {
+                let (id, sender) = (unit.id, unit.sender.clone());
+                thread::spawn(move || Self::read(id, sender, stream, max_size))
+            }
+            Err(err) => {
+                println!("ERR>> {err}");
+                if unit.disconnect().is_ok() {
+                    Self::wait_disconnect(unit);
+                }
+                return;
+            }
+        };
 
-        loop {
-            let sig = sig_rx.recv_timeout(Duration::from_millis(CLOCK));
-            match sig {
-                Ok(signal) => {
-                    println!("received signal from other stream...");
+        for signal in unit.receiver.iter() {
+            let written = match signal {
+                Signal::Deliver { msg, .. } => Self::write(&mut wc.stream, msg),
+                Signal::Stats(stats) => Self::write(&mut wc.stream, format!("{stats:?}")),
+                Signal::Disconnect { .. } => break,
+                signal => {
+                    println!("ERR>> unexpected signal {signal:?}");
+                    Ok(())
                 }
-                Err(_) => {
-                    let frame = wc.receive();
-                    println!("received msg from client...");
-                    println!("{frame}");
+            };
+            if let Err(err) = written {
+                // reader gets EOF and disconnects the unit
+                println!("ERR>> {err}");
+                let _ = wc.stream.shutdown(Shutdown::Both);
+            }
+        }
+
+        let _ = wc.stream.shutdown(Shutdown::Both);
+        let _ = reader.join();
+    }
+
+    /// drop signals until the commander acknowledges the `Disconnect` of `unit`
+    fn wait_disconnect(unit: &Unit) {
+        for signal in unit.receiver.iter() {
+            if let Signal::Disconnect { .. } = signal {
+                break;
+            }
+        }
+    }
+
+    /// forward client frames to the commander until the client leaves
+    fn read<S: NetStream>(id: ConnectionId, sender: Sender<Signal>, stream: S, max_size: usize) {
+        let mut wc = WebsocketConnection::new(stream, Some(max_size));
+        while let Ok(frame) = wc.try_receive() {
+            match frame.header.opcode {
+                Opcode::Control(Control::Close) => break,
+                Opcode::Data(Data::Text) => {
+                    let msg = String::from_utf8_lossy(&frame.payload).into_owned();
+                    let broadcast = Signal::Broadcast {
+                        from: id,
+                        room: None,
+                        msg,
+                    };
+                    if sender.send(broadcast).is_err() {
+                        break;
+                    }
                 }
+                _ => {}
             }
         }
+        let _ = sender.send(Signal::Disconnect { id });
+    }
+
+    /// write text frame in a single write
+    fn write<S: NetStream>(stream: &mut S, msg: String) -> io::Result<()> {
+        let mut raw = Vec::new();
+        Frame::create_msg_frame(msg).format(&mut raw)?;
+        stream.write_all(&raw)
     }
 }
 
+/// connection slots, spawned on demand up to the number of units
 pub struct ConnectionPool {
-    connections: Vec<Connection>,
-    job_tx: Sender<TcpStream>,
+    pub connections: Vec<Connection>,
+    /// units of slots not spawned yet
+    units: Vec<Unit>,
+    /// slots waiting for a stream, minus streams handed over and not
+    /// received yet (negative while the backlog is not empty)
+    idle: Arc<AtomicIsize>,
+    /// largest frame payload accepted from a client
+    max_size: usize,
+    job_tx: SyncSender<Box<dyn Accepted>>,
+    job_rx: Arc<Mutex<Receiver<Box<dyn Accepted>>>>,
 }
 impl ConnectionPool {
-    pub fn build(
-        size: usize,
-        mut transceivers: Vec<Option<Unit<Box<dyn Send>>>>,
-    ) -> ConnectionPool {
-        let (job_tx, job_rx) = mpsc::channel();
-        let job_rx = Arc::new(Mutex::new(job_rx));
-        let mut connections = Vec::with_capacity(size);
-
-        for idx in 0..size {
-            let trsv = transceivers[idx].take().unwrap();
-            let con =
-                Connection::build(Arc::clone(&job_rx), Some(trsv.sender), Some(trsv.receiver));
-            connections.push(con)
-        }
+    /// `backlog` : accepted streams waiting for a free slot,
+    /// `max_size` : largest frame payload accepted from a client
+    pub fn build(backlog: usize, max_size: usize, units: Vec<Option<Unit>>) -> ConnectionPool {
+        let (job_tx, job_rx) = mpsc::sync_channel(backlog);
+        let mut units: Vec<Unit> = units.into_iter().flatten().collect();
+        // spawned in order of unit id
+        units.reverse();
 
         ConnectionPool {
-            connections,
+            connections: Vec::with_capacity(units.len()),
+            units,
+            idle: Arc::new(AtomicIsize::new(0)),
+            max_size,
             job_tx,
+            job_rx: Arc::new(Mutex::new(job_rx)),
         }
     }
 
-    pub fn catch_connection(&self, stream: TcpStream) {
-        self.job_tx.send(stream);
+    /// hand stream to an idle slot, a new slot, or the backlog
+    ///
+    /// answers with 503 when every slot is busy and the backlog is full
+    pub fn catch_connection<S: NetStream>(&mut self, stream: S) {
+        let stream: Box<dyn Accepted> = Box::new(stream);
+        // only the pool decreases `idle` : a positive count stays positive
+        // until this stream is sent, and the idle slot takes it even if it
+        // is not blocked in `recv` yet
+        if self.idle.load(Ordering::SeqCst) > 0 {
+            self.idle.fetch_sub(1, Ordering::SeqCst);
+            if let Err(mpsc::SendError(stream)) = self.job_tx.send(stream) {
+                stream.reject();
+            }
+            return;
+        }
+        if let Some(unit) = self.units.pop() {
+            let con = Connection::build(
+                self.job_rx.clone(),
+                self.idle.clone(),
+                self.max_size,
+                unit,
+                stream,
+            );
+            self.connections.push(con);
+            return;
+        }
+
+        match self.job_tx.try_send(stream) {
+            // taken by the next slot becoming idle
+            Ok(()) => {
+                self.idle.fetch_sub(1, Ordering::SeqCst);
+            }
+            Err(TrySendError::Full(stream)) | Err(TrySendError::Disconnected(stream)) => {
+                println!("ERR>> every connection slot is busy");
+                stream.reject();
+            }
+        }
     }
 }
 
 pub struct Server {
     config: Config,
 
-    senders: Option<Vec<mpsc::Sender<Box<dyn Send>>>>,
-    receiver: Option<mpsc::Receiver<Box<dyn Send>>>,
+    /// routes signals between connections
+    commander: Option<Commander>,
 
     connections: ConnectionPool,
 }
@@ -138,56 +263,338 @@ impl Server {
     pub fn build(config: Config) -> Self {
         let army = Army::build(config.max_connection);
 
-        let root = army.commander;
-
-        let connections = ConnectionPool::build(config.max_connection, army.units);
+        let connections =
+            ConnectionPool::build(config.backlog, config.max_payload_size, army.units);
 
         Server {
             config,
-            senders: Some(root.senders),
-            receiver: Some(root.receiver),
+            commander: Some(army.commander),
             connections,
         }
     }
 
-    pub fn manage(
-        senders: Vec<mpsc::Sender<Box<dyn Send>>>,
-        receiver: mpsc::Receiver<Box<dyn Send>>,
-    ) {
-        loop {
-            let sig = receiver.recv().unwrap();
+    /// route signals between connections
+    pub fn manage(commander: Commander) {
+        commander.run()
+    }
 
-            // manage signals here
-            if true {
-                senders[0].send(Box::new(Signal {
-                    something: String::from("something"),
-                }));
-            }
+    /// bind `config.url` : `unix:<path>` for a Unix domain socket,
+    /// a TCP address otherwise
+    pub fn listen(&mut self) -> io::Result<()> {
+        #[cfg(unix)]
+        if let Some(path) = self.config.url.strip_prefix("unix:") {
+            let listener = bind_unix(path)?;
+            return self.serve(listener);
         }
+        let listener = TcpListener::bind(&self.config.url)?;
+        self.serve(listener)
     }
 
-    pub fn listen(&mut self) {
-        let listener = TcpListener::bind(self.config.url.clone()).unwrap();
-
+    /// serve connections accepted on `listener`
+    ///
+    /// a server is served once : a second call fails
+    pub fn serve<L: Listener>(&mut self, listener: L) -> io::Result<()> {
         // create event manager
-        let snd = self.senders.take().unwrap();
-        let rsv = self.receiver.take().unwrap();
-        thread::spawn(move || self::Server::manage(snd.clone(), rsv));
+        let Some(commander) = self.commander.take() else {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "server is already serving",
+            ));
+        };
+        thread::spawn(move || self::Server::manage(commander));
 
-        for stream in listener.incoming() {
-            let stream = stream.unwrap();
-            self.connections.catch_connection(stream)
+        loop {
+            match listener.accept() {
+                Ok(stream) => self.connections.catch_connection(stream),
+                Err(err) => accept_failed(&err),
+            }
         }
     }
 }
 
 #[test]
 fn test_sio_1() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = Listener::local_addr(&listener).unwrap();
     let config = Config {
-        url: String::from("127.0.0.1:8001"),
+        url: addr.to_string(),
         max_connection: 1000,
+        backlog: 0,
         max_payload_size: 1024,
     };
     let mut srv = Server::build(config);
-    srv.listen();
+    thread::spawn(move || srv.serve(listener));
+
+    let mut client = std::net::TcpStream::connect(addr.to_string()).unwrap();
+    io::Write::write_all(&mut client, b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n").unwrap();
+    let mut line = String::new();
+    io::BufRead::read_line(&mut io::BufReader::new(client), &mut line).unwrap();
+    assert!(line.starts_with("HTTP/1.1 101"));
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::{SocketAddr, TcpStream},
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    fn open(addr: SocketAddr) -> TcpStream {
+        let mut client = TcpStream::connect(addr).unwrap();
+        client
+            .write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+            .unwrap();
+        client
+    }
+
+    /// status line of the response to `client` (empty on error)
+    fn status(client: &TcpStream) -> String {
+        let mut line = String::new();
+        BufReader::new(client)
+            .read_line(&mut line)
+            .unwrap_or_default();
+        line
+    }
+
+    #[test]
+    fn push_without_client_activity() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut srv = Server::build(Config {
+            url: addr.to_string(),
+            max_connection: 2,
+            backlog: 0,
+            max_payload_size: 1024,
+        });
+        thread::spawn(move || srv.serve(listener));
+
+        let mut sender = open(addr);
+        let mut sender_reader = BufReader::new(sender.try_clone().unwrap());
+        let receiver = open(addr);
+        let mut receiver_reader = BufReader::new(receiver.try_clone().unwrap());
+        for reader in [&mut sender_reader, &mut receiver_reader] {
+            let mut line = String::from("-");
+            while line != "\r\n" {
+                line.clear();
+                reader.read_line(&mut line).unwrap();
+            }
+        }
+
+        // masked with zero key, payload stays as is
+        sender.write_all(b"\x81\x85\0\0\0\0hello").unwrap();
+
+        // idle receiver is woken up by the broadcast
+        receiver
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut frame = [0u8; 7];
+        receiver_reader.read_exact(&mut frame).unwrap();
+        assert_eq!(&frame, b"\x81\x05hello");
+    }
+
+    #[test]
+    fn reuse_slots_and_reject_when_busy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut srv = Server::build(Config {
+            url: addr.to_string(),
+            max_connection: 2,
+            backlog: 0,
+            max_payload_size: 1024,
+        });
+        thread::spawn(move || srv.serve(listener));
+
+        let first = open(addr);
+        assert!(status(&first).contains("101"));
+        let second = open(addr);
+        assert!(status(&second).contains("101"));
+
+        let mut rejected = String::new();
+        TcpStream::connect(addr)
+            .unwrap()
+            .read_to_string(&mut rejected)
+            .unwrap();
+        assert!(rejected.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // slot of the first client serves the next one
+        drop(first);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !status(&open(addr)).contains("101") {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        }
+        drop(second);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn serve_websocket_on_unix_socket() {
+        use std::os::unix::net::UnixStream;
+
+        let path =
+            std::env::temp_dir().join(format!("sockets-server2-{}.sock", std::process::id()));
+        let listener = bind_unix(&path).unwrap();
+        let mut srv = Server::build(Config {
+            url: format!("unix:{}", path.display()),
+            max_connection: 2,
+            backlog: 0,
+            max_payload_size: 1024,
+        });
+        thread::spawn(move || srv.serve(listener));
+
+        let mut clients: Vec<BufReader<UnixStream>> = (0..2)
+            .map(|_| {
+                let mut client = UnixStream::connect(&path).unwrap();
+                client
+                    .write_all(b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n")
+                    .unwrap();
+                client
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                let mut reader = BufReader::new(client);
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+                assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+                assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+                reader
+            })
+            .collect();
+
+        clients[0]
+            .get_mut()
+            .write_all(b"\x81\x82\0\0\0\0hi")
+            .unwrap();
+        let mut frame = [0u8; 4];
+        clients[1].read_exact(&mut frame).unwrap();
+        assert_eq!(&frame, b"\x81\x02hi");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_handshake_does_not_end_next_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut srv = Server::build(Config {
+            url: addr.to_string(),
+            max_connection: 1,
+            backlog: 1,
+            max_payload_size: 1024,
+        });
+        thread::spawn(move || srv.serve(listener));
+
+        for _ in 0..20 {
+            // not a websocket upgrade : the slot disconnects its unit
+            let mut broken = TcpStream::connect(addr).unwrap();
+            broken.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut answer = String::new();
+            broken.read_to_string(&mut answer).unwrap_or_default();
+
+            // acknowledgement of that disconnect does not end this client
+            let client = open(addr);
+            assert!(status(&client).contains("101"));
+            client
+                .set_read_timeout(Some(Duration::from_millis(20)))
+                .unwrap();
+            let err = (&client).read(&mut [0u8; 1]).unwrap_err();
+            assert!(matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ));
+        }
+    }
+
+    #[test]
+    fn close_clients_sending_oversized_frames() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut srv = Server::build(Config {
+            url: addr.to_string(),
+            max_connection: 2,
+            backlog: 0,
+            max_payload_size: 4,
+        });
+        thread::spawn(move || srv.serve(listener));
+
+        let mut sender = open(addr);
+        assert!(status(&sender).contains("101"));
+        let receiver = open(addr);
+        assert!(status(&receiver).contains("101"));
+
+        sender.write_all(b"\x81\x85\0\0\0\0hello").unwrap();
+        sender
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        // frame is refused and the client disconnected (EOF)
+        let mut rest = Vec::new();
+        sender.read_to_end(&mut rest).unwrap();
+
+        receiver
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        assert!((&receiver).read(&mut [0u8; 1]).is_err());
+    }
+
+    #[test]
+    fn serve_only_once() {
+        let mut srv = Server::build(Config {
+            url: String::from("127.0.0.1:0"),
+            max_connection: 1,
+            backlog: 0,
+            max_payload_size: 1024,
+        });
+        // commander already taken, as by a first `serve`
+        srv.commander.take();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let err = srv.serve(listener).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn reused_slot_gets_nothing_meant_for_previous_client() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut srv = Server::build(Config {
+            url: addr.to_string(),
+            max_connection: 2,
+            backlog: 0,
+            max_payload_size: 1024,
+        });
+        thread::spawn(move || srv.serve(listener));
+
+        // first client takes slot 0, the sender slot 1
+        let first = open(addr);
+        assert!(status(&first).contains("101"));
+        let mut sender = open(addr);
+        assert!(status(&sender).contains("101"));
+
+        drop(first);
+        sender.write_all(b"\x81\x85\0\0\0\0stale").unwrap();
+
+        // slot 0 serves the next client once the first one is gone
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut next = loop {
+            let mut next = BufReader::new(open(addr));
+            let mut head = String::new();
+            while !head.ends_with("\r\n\r\n") && next.read_line(&mut head).unwrap_or(0) > 0 {}
+            if head.starts_with("HTTP/1.1 101") {
+                break next;
+            }
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(10));
+        };
+        sender.write_all(b"\x81\x85\0\0\0\0fresh").unwrap();
+
+        next.get_ref()
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut frame = [0u8; 7];
+        next.read_exact(&mut frame).unwrap();
+        assert_eq!(&frame, b"\x81\x05fresh");
+    }
 }
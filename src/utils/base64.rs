@@ -1,30 +1,113 @@
-use std::vec;
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    vec,
+};
 
+/// standard alphabet with padding, see `Config` for the other variants
 pub struct Base64;
 
 const OFFSET_UPPER: u8 = 65;
 const OFFSET_LOWER: u8 = 71;
 const OFFSET_DIGIT: u8 = 4;
 
+/// bytes read at once when streaming (multiple of 3 and 4)
+const STREAM_CHUNK: usize = 3 * 4 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alphabet {
+    /// `+` and `/` (RFC 4648 section 4)
+    Standard,
+    /// `-` and `_` (RFC 4648 section 5)
+    UrlSafe,
+}
+
+/// alphabet and padding of an encoding
+///
+/// decoding is strict : padding must be present exactly when `padding`
+/// is set, and whitespace or unused trailing bits are rejected
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Config {
+    pub alphabet: Alphabet,
+    pub padding: bool,
+}
+
+pub const STANDARD: Config = Config {
+    alphabet: Alphabet::Standard,
+    padding: true,
+};
+pub const STANDARD_NO_PAD: Config = Config {
+    alphabet: Alphabet::Standard,
+    padding: false,
+};
+pub const URL_SAFE: Config = Config {
+    alphabet: Alphabet::UrlSafe,
+    padding: true,
+};
+pub const URL_SAFE_NO_PAD: Config = Config {
+    alphabet: Alphabet::UrlSafe,
+    padding: false,
+};
+
+/// reason `decode` failed, indexes are offsets in the encoded input
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    /// byte outside of the alphabet (whitespace included)
+    InvalidByte { index: usize, byte: u8 },
+    /// input length can not be produced by the encoding
+    InvalidLength(usize),
+    /// padding missing, misplaced or not allowed
+    InvalidPadding { index: usize },
+    /// last symbol has non zero unused bits
+    InvalidLastSymbol { index: usize, byte: u8 },
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::InvalidByte { index, byte } => {
+                write!(f, "invalid base64 byte {byte:#04x} at {index}")
+            }
+            DecodeError::InvalidLength(len) => write!(f, "invalid base64 length {len}"),
+            DecodeError::InvalidPadding { index } => write!(f, "invalid base64 padding at {index}"),
+            DecodeError::InvalidLastSymbol { index, byte } => {
+                write!(f, "invalid last base64 symbol {byte:#04x} at {index}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
 impl Base64 {
-    fn __base64_to_char(&self, base64: u8) -> Option<char> {
-        let char = match base64 {
-            0..=25 => base64 + OFFSET_UPPER,  // A-Z
-            26..=51 => base64 + OFFSET_LOWER, // a-z
-            52..=61 => base64 - OFFSET_DIGIT, // 0-9
-            62 => 43,                         // +
-            63 => 47,                         // /
+    fn __base64_to_char(&self, base64: u8, alphabet: Alphabet) -> Option<char> {
+        let char = match (base64, alphabet) {
+            (0..=25, _) => base64 + OFFSET_UPPER,  // A-Z
+            (26..=51, _) => base64 + OFFSET_LOWER, // a-z
+            (52..=61, _) => base64 - OFFSET_DIGIT, // 0-9
+            (62, Alphabet::Standard) => 43,        // +
+            (63, Alphabet::Standard) => 47,        // /
+            (62, Alphabet::UrlSafe) => 45,         // -
+            (63, Alphabet::UrlSafe) => 95,         // _
             _ => return None,
         };
         Some(char as char)
     }
-    fn __char_to_base64(&self, char: char) -> Option<u8> {
-        let base64 = match char as u8 {
-            43 => 62,                              // +
-            47 => 63,                              // /
-            48..=57 => char as u8 + OFFSET_DIGIT,  // 0-9
-            65..=90 => char as u8 - OFFSET_UPPER,  // A-Z
-            97..=122 => char as u8 - OFFSET_LOWER, // a-z
+    fn __char_to_base64(&self, char: u8, alphabet: Alphabet) -> Option<u8> {
+        let base64 = match (char, alphabet) {
+            (43, Alphabet::Standard) => 62,       // +
+            (47, Alphabet::Standard) => 63,       // /
+            (45, Alphabet::UrlSafe) => 62,        // -
+            (95, Alphabet::UrlSafe) => 63,        // _
+            (48..=57, _) => char + OFFSET_DIGIT,  // 0-9
+            (65..=90, _) => char - OFFSET_UPPER,  // A-Z
+            (97..=122, _) => char - OFFSET_LOWER, // a-z
             _ => return None,
         };
         Some(base64)
@@ -49,30 +132,203 @@ impl Base64 {
         }
     }
 
-    fn __encode_chunk(&self, chunk: Vec<u8>) -> Vec<char> {
+    fn __encode_chunk(&self, chunk: Vec<u8>, config: Config) -> Vec<char> {
         let mut out = vec!['='; 4];
 
         for idx in 0..chunk.len() {
-            if let Some(char) = self.__base64_to_char(chunk[idx]) {
+            if let Some(char) = self.__base64_to_char(chunk[idx], config.alphabet) {
                 out[idx] = char;
             }
         }
+        if !config.padding {
+            out.truncate(chunk.len());
+        }
         out
     }
 
+    /// decode `input` (the last group when `last`) into `out`,
+    /// `offset` is the position of `input` in the whole encoded data
+    fn __decode_groups(
+        &self,
+        input: &[u8],
+        offset: usize,
+        last: bool,
+        config: Config,
+        out: &mut Vec<u8>,
+    ) -> Result<(), DecodeError> {
+        // report bytes outside of the alphabet before length errors
+        if let Some((idx, byte)) = input
+            .iter()
+            .enumerate()
+            .find(|(_, b)| **b != b'=' && self.__char_to_base64(**b, config.alphabet).is_none())
+        {
+            return Err(DecodeError::InvalidByte {
+                index: offset + idx,
+                byte: *byte,
+            });
+        }
+
+        let mut symbols = input;
+        if last && config.padding {
+            if !input.len().is_multiple_of(4) {
+                return Err(DecodeError::InvalidLength(offset + input.len()));
+            }
+            let pad = input
+                .iter()
+                .rev()
+                .take(2)
+                .take_while(|b| **b == b'=')
+                .count();
+            symbols = &input[..input.len() - pad];
+        }
+        if last && symbols.len() % 4 == 1 {
+            return Err(DecodeError::InvalidLength(offset + input.len()));
+        }
+
+        let mut values = Vec::with_capacity(4);
+        for (group_idx, group) in symbols.chunks(4).enumerate() {
+            values.clear();
+            for (idx, byte) in group.iter().enumerate() {
+                let index = offset + group_idx * 4 + idx;
+                match self.__char_to_base64(*byte, config.alphabet) {
+                    Some(value) => values.push(value),
+                    None => return Err(DecodeError::InvalidPadding { index }),
+                }
+            }
+            // unused low bits of the last symbol of a partial group
+            let unused = match values.len() {
+                2 => values[1] & 0b00001111,
+                3 => values[2] & 0b00000011,
+                _ => 0,
+            };
+            if unused != 0 {
+                let index = offset + group_idx * 4 + values.len() - 1;
+                let byte = group[values.len() - 1];
+                return Err(DecodeError::InvalidLastSymbol { index, byte });
+            }
+
+            let bytes = [
+                values[0] << 2 | values[1] >> 4,
+                values[1] << 4 | values.get(2).unwrap_or(&0) >> 2,
+                values.get(2).unwrap_or(&0) << 6 | values.get(3).unwrap_or(&0),
+            ];
+            out.extend_from_slice(&bytes[..values.len() - 1]);
+        }
+        Ok(())
+    }
+
     pub fn encode(&self, data: &[u8]) -> String {
+        self.encode_with(data, STANDARD)
+    }
+
+    pub fn encode_with(&self, data: &[u8], config: Config) -> String {
         let encoded = data
             .chunks(3)
             .map(|chunk| self.__split(chunk))
-            .flat_map(|chunk| self.__encode_chunk(chunk));
+            .flat_map(|chunk| self.__encode_chunk(chunk, config));
 
         String::from_iter(encoded)
     }
+
+    /// decode standard base64 with padding
+    pub fn decode(&self, data: &str) -> Result<Vec<u8>, DecodeError> {
+        self.decode_with(data, STANDARD)
+    }
+
+    pub fn decode_with(&self, data: &str, config: Config) -> Result<Vec<u8>, DecodeError> {
+        let mut out = Vec::with_capacity(data.len() / 4 * 3 + 2);
+        self.__decode_groups(data.as_bytes(), 0, true, config, &mut out)?;
+        Ok(out)
+    }
+
+    /// encode everything read from `reader` into `writer`,
+    /// returns the number of bytes written
+    pub fn encode_stream<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        config: Config,
+    ) -> io::Result<u64> {
+        let mut buf = vec![0u8; STREAM_CHUNK];
+        let mut filled = 0;
+        let mut written = 0;
+        loop {
+            let len = match reader.read(&mut buf[filled..]) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            filled += len;
+            // encode whole groups only, except at the end
+            let ready = if len == 0 {
+                filled
+            } else {
+                filled - filled % 3
+            };
+            if ready > 0 && (len == 0 || filled == buf.len()) {
+                let encoded = self.encode_with(&buf[..ready], config);
+                writer.write_all(encoded.as_bytes())?;
+                written += encoded.len() as u64;
+                buf.copy_within(ready..filled, 0);
+                filled -= ready;
+            }
+            if len == 0 {
+                return Ok(written);
+            }
+        }
+    }
+
+    /// decode everything read from `reader` into `writer`,
+    /// returns the number of bytes written
+    pub fn decode_stream<R: Read, W: Write>(
+        &self,
+        reader: &mut R,
+        writer: &mut W,
+        config: Config,
+    ) -> io::Result<u64> {
+        let mut chunk = vec![0u8; STREAM_CHUNK];
+        let mut pending = Vec::new();
+        let mut offset = 0;
+        let mut out = Vec::new();
+        let mut written = 0;
+        loop {
+            let len = match reader.read(&mut chunk) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if len == 0 {
+                break;
+            }
+            pending.extend_from_slice(&chunk[..len]);
+
+            // the last group may hold padding : keep it until the end
+            let keep = match pending.len() % 4 {
+                0 => 4,
+                rem => rem,
+            };
+            if pending.len() > keep {
+                let ready = pending.len() - keep;
+                out.clear();
+                self.__decode_groups(&pending[..ready], offset, false, config, &mut out)?;
+                writer.write_all(&out)?;
+                written += out.len() as u64;
+                pending.drain(..ready);
+                offset += ready;
+            }
+        }
+        out.clear();
+        self.__decode_groups(&pending, offset, true, config, &mut out)?;
+        writer.write_all(&out)?;
+        Ok(written + out.len() as u64)
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Base64;
+    use std::io::Cursor;
+
+    use super::*;
 
     #[test]
     fn base64_encode() {
@@ -80,4 +336,80 @@ mod test {
         println!("encoded: {}", encoded);
         assert_eq!(encoded, "aGVsbG8gd29ybGQ=");
     }
+
+    #[test]
+    fn base64_decode() {
+        for data in [&b""[..], b"f", b"fo", b"foo", b"foob", b"fooba", b"foobar"] {
+            for config in [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD] {
+                let encoded = Base64.encode_with(data, config);
+                assert_eq!(Base64.decode_with(&encoded, config).unwrap(), data);
+            }
+        }
+        assert_eq!(Base64.encode_with(&[0xfb, 0xff], URL_SAFE_NO_PAD), "-_8");
+        assert_eq!(Base64.encode_with(&[0xfb, 0xff], STANDARD), "+/8=");
+        assert_eq!(
+            Base64.decode("dGhlIHNhbXBsZSBub25jZQ==").unwrap(),
+            b"the sample nonce"
+        );
+
+        let err = |data: &str, config| Base64.decode_with(data, config).unwrap_err();
+        assert_eq!(
+            err("aGVs bG8=", STANDARD),
+            DecodeError::InvalidByte {
+                index: 4,
+                byte: b' '
+            }
+        );
+        assert_eq!(err("aGVsbG8", STANDARD), DecodeError::InvalidLength(7));
+        assert_eq!(
+            err("aGVsbG8=", STANDARD_NO_PAD),
+            DecodeError::InvalidPadding { index: 7 }
+        );
+        assert_eq!(
+            err("aG=sbG8=", STANDARD),
+            DecodeError::InvalidPadding { index: 2 }
+        );
+        assert_eq!(
+            err("a===", STANDARD),
+            DecodeError::InvalidPadding { index: 1 }
+        );
+        assert_eq!(err("aGVsb", STANDARD_NO_PAD), DecodeError::InvalidLength(5));
+        assert_eq!(
+            err("aGVsbG9=", STANDARD),
+            DecodeError::InvalidLastSymbol {
+                index: 6,
+                byte: b'9'
+            }
+        );
+        assert!(matches!(
+            err("-_8=", STANDARD),
+            DecodeError::InvalidByte { index: 0, .. }
+        ));
+    }
+
+    #[test]
+    fn base64_stream() {
+        let data: Vec<u8> = (0..40_000u32).map(|i| (i * 7 % 256) as u8).collect();
+
+        let mut encoded = Vec::new();
+        let len = Base64
+            .encode_stream(&mut Cursor::new(&data), &mut encoded, STANDARD)
+            .unwrap();
+        assert_eq!(len as usize, encoded.len());
+        assert_eq!(encoded, Base64.encode(&data).into_bytes());
+
+        let mut decoded = Vec::new();
+        Base64
+            .decode_stream(&mut Cursor::new(&encoded), &mut decoded, STANDARD)
+            .unwrap();
+        assert_eq!(decoded, data);
+
+        // padding in the middle of the stream
+        let mut bad = Base64.encode(b"ab").into_bytes();
+        bad.extend_from_slice(&encoded);
+        let err = Base64
+            .decode_stream(&mut Cursor::new(&bad), &mut Vec::new(), STANDARD)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
 }
@@ -0,0 +1,147 @@
+use super::{
+    base64::{Base64, URL_SAFE_NO_PAD},
+    sha1::{Sha1, ShaOutput},
+};
+
+/// block size of SHA-1
+const BLOCK_SIZE: usize = 64;
+const IPAD: u8 = 0x36;
+const OPAD: u8 = 0x5c;
+
+/// HMAC-SHA1 (RFC 2104), fed incrementally like `Sha1`
+#[derive(Debug, Clone)]
+pub struct HmacSha1 {
+    inner: Sha1,
+    outer: Sha1,
+}
+
+impl HmacSha1 {
+    pub fn new(key: &[u8]) -> Self {
+        // keys longer than a block are hashed first
+        let mut block = [0u8; BLOCK_SIZE];
+        if key.len() > BLOCK_SIZE {
+            block[..20].copy_from_slice(&Sha1::from(key).finalize().as_byte());
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner = Sha1::new();
+        inner.update(&block.map(|b| b ^ IPAD));
+        let mut outer = Sha1::new();
+        outer.update(&block.map(|b| b ^ OPAD));
+        HmacSha1 { inner, outer }
+    }
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+    pub fn finalize(self) -> ShaOutput {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finalize().as_byte());
+        outer.finalize()
+    }
+    /// check `mac` in constant time
+    pub fn verify(self, mac: &[u8]) -> bool {
+        constant_time_eq(&self.finalize().as_byte(), mac)
+    }
+}
+
+/// HMAC-SHA1 of `data`
+pub fn hmac_sha1(key: &[u8], data: &[u8]) -> [u8; 20] {
+    let mut mac = HmacSha1::new(key);
+    mac.update(data);
+    mac.finalize().as_byte()
+}
+
+/// compare without stopping at the first difference,
+/// so timing does not tell how much of a secret matched
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let diff = a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    // keep the compiler from short-circuiting the fold
+    std::hint::black_box(diff) == 0
+}
+
+/// `msg.mac` token, the mac being url-safe base64
+pub fn sign_token(key: &[u8], msg: &str) -> String {
+    let mac = hmac_sha1(key, msg.as_bytes());
+    format!("{msg}.{}", Base64.encode_with(&mac, URL_SAFE_NO_PAD))
+}
+
+/// message of a token created by `sign_token` with the same key
+pub fn verify_token<'a>(key: &[u8], token: &'a str) -> Option<&'a str> {
+    let (msg, mac) = token.rsplit_once('.')?;
+    let mac = Base64.decode_with(mac, URL_SAFE_NO_PAD).ok()?;
+    let mut hmac = HmacSha1::new(key);
+    hmac.update(msg.as_bytes());
+    hmac.verify(&mac).then_some(msg)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hmac_rfc2202_vectors() {
+        let key4: Vec<u8> = (1..=25).collect();
+        let vectors: [(&[u8], &[u8], &str); 7] = [
+            (
+                &[0x0b; 20],
+                b"Hi There",
+                "b617318655057264e28bc0b6fb378c8ef146be00",
+            ),
+            (
+                b"Jefe",
+                b"what do ya want for nothing?",
+                "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79",
+            ),
+            (
+                &[0xaa; 20],
+                &[0xdd; 50],
+                "125d7342b9ac11cd91a39af48aa17b4f63f175d3",
+            ),
+            (
+                &key4,
+                &[0xcd; 50],
+                "4c9007f4026250c6bc8414f9bf50c86c2d7235da",
+            ),
+            (
+                &[0x0c; 20],
+                b"Test With Truncation",
+                "4c1a03424b55e07fe7f27be1d58bb9324a9a5a04",
+            ),
+            (
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key - Hash Key First",
+                "aa4ae5e15272d00e95705637ce8a3b55ed402112",
+            ),
+            (
+                &[0xaa; 80],
+                b"Test Using Larger Than Block-Size Key and Larger Than One Block-Size Data",
+                "e8e99d0f45237d786d6bbaa7965c7808bbff1a91",
+            ),
+        ];
+        for (key, data, expected) in vectors {
+            let mut mac = HmacSha1::new(key);
+            mac.update(data);
+            assert_eq!(mac.finalize().to_hexstring(), expected);
+        }
+    }
+
+    #[test]
+    fn sign_and_verify_token() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+
+        let token = sign_token(b"secret", "sid123");
+        assert_eq!(verify_token(b"secret", &token), Some("sid123"));
+        assert_eq!(verify_token(b"other", &token), None);
+        assert_eq!(
+            verify_token(b"secret", &token.replace("sid123", "sid124")),
+            None
+        );
+        assert_eq!(verify_token(b"secret", "sid123"), None);
+    }
+}
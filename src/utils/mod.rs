@@ -1,2 +1,3 @@
 pub mod base64;
+pub mod hmac;
 pub mod sha1;
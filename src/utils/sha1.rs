@@ -1,4 +1,7 @@
-use std::{fmt::Display, format};
+use std::{
+    format,
+    io::{self, Write},
+};
 
 // initial states
 const HASH: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
@@ -8,18 +11,20 @@ const K1: u32 = 0x6ED9EBA1u32;
 const K2: u32 = 0x8F1BBCDCu32;
 const K3: u32 = 0xCA62C1D6u32;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Sha1 {
     /// internal state 160(32*5)
     state: [u32; 5],
-    /// 512bits
-    blocks: [u8; 64],
-
-    len: usize,
+    /// bytes hashed so far
+    len: u64,
+    /// start of the next block
+    block: [u8; 64],
+    /// bytes used in `block`
+    buffered: usize,
 }
 
 pub fn __rotate_left_u32(value: u32, bits: usize) -> u32 {
-    (value << (bits)) | (value) >> (32 - (bits))
+    value.rotate_left(bits as u32)
 }
 
 pub fn __trim_to_64(input: &[u8]) -> &[u8; 64] {
@@ -35,7 +40,8 @@ impl Default for Sha1 {
         Sha1 {
             state: HASH,
             len: 0,
-            blocks: [0; 64],
+            block: [0; 64],
+            buffered: 0,
         }
     }
 }
@@ -47,118 +53,136 @@ impl Sha1 {
 impl Sha1 {
     pub fn from<T: AsRef<[u8]>>(data: T) -> Self {
         let mut sha = Sha1::default();
-        sha.__generate(data.as_ref());
+        sha.update(data.as_ref());
         sha
     }
 
-    /// pre -> process -> digest
+    /// hash `data`, chunks may have any size
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u64;
+
+        if self.buffered > 0 {
+            let take = data.len().min(64 - self.buffered);
+            self.block[self.buffered..self.buffered + take].copy_from_slice(&data[..take]);
+            self.buffered += take;
+            data = &data[take..];
+            if self.buffered < 64 {
+                return;
+            }
+            let block = self.block;
+            self.__process_block(&block);
+            self.buffered = 0;
+        }
 
-    fn __preprocess_tail(&mut self, tail: &mut [u8; 128], tailbytes: usize) {
-        let bits = self.len;
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.__process_block(__trim_to_64(block));
+        }
+        let rest = blocks.remainder();
+        self.block[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// digest of everything hashed so far
+    pub fn finalize(mut self) -> ShaOutput {
+        self.__pad();
+        self.__output()
+    }
+
+    /// start over with an empty message
+    pub fn reset(&mut self) {
+        *self = Sha1::default();
+    }
 
+    // pad -> process -> digest
+
+    /// `0x80`, zeros and the message length in bits, as the last block(s)
+    fn __pad(&mut self) {
+        let bits = self.len.wrapping_mul(8);
+
+        let mut tail = [0u8; 128];
         tail[0] = 0x80;
-        tail[tailbytes - 8] = ((bits >> 56) & 0xFF) as u8;
-        tail[tailbytes - 7] = ((bits >> 48) & 0xFF) as u8;
-        tail[tailbytes - 6] = ((bits >> 40) & 0xFF) as u8;
-        tail[tailbytes - 5] = ((bits >> 32) & 0xFF) as u8;
-        tail[tailbytes - 4] = ((bits >> 24) & 0xFF) as u8;
-        tail[tailbytes - 3] = ((bits >> 16) & 0xFF) as u8;
-        tail[tailbytes - 2] = ((bits >> 8) & 0xFF) as u8;
-        tail[tailbytes - 1] = (bits & 0xFF) as u8;
-    }
-
-    fn __generate(&mut self, data: &[u8]) {
-        let data_bytes = data.len();
-        self.len = data_bytes * 8;
-
-        let iter_count = (data_bytes + 8) / 64 + 1;
-        let tailbytes = 64 * iter_count - data_bytes;
-        let mut tail: [u8; 128] = [0; 128];
-
-        self.__preprocess_tail(&mut tail, tailbytes);
-
-        let mut didx = 0;
-        for _ in 0..iter_count {
-            let mut words = [0u32; 80];
-
-            for widx in 0..16 {
-                let mut wcount = 24;
-
-                while didx < data_bytes && wcount >= 0 {
-                    words[widx] += u32::from(data[didx]) << wcount;
-                    didx += 1;
-                    wcount -= 8;
-                }
-
-                while wcount >= 0 {
-                    words[widx] += u32::from(tail[didx - data_bytes]) << wcount;
-                    didx += 1;
-                    wcount -= 8;
-                }
-            }
+        let tailbytes = if self.buffered < 56 { 64 } else { 128 } - self.buffered;
+        tail[tailbytes - 8..tailbytes].copy_from_slice(&bits.to_be_bytes());
 
-            //* extend u32*16 into u32*80
-            //* Extend the sixteen 32-bit words into eighty 32-bit words, with potential optimization from:
-            //* "Improving the Performance of the Secure Hash Algorithm (SHA-1)" by Max Locktyukhin
-            for widx in 16..32 {
-                words[widx] = __rotate_left_u32(
-                    words[widx - 3] ^ words[widx - 8] ^ words[widx - 14] ^ words[widx - 16],
-                    1,
-                );
-            }
-            for widx in 32..80 {
-                words[widx] = __rotate_left_u32(
-                    words[widx - 6] ^ words[widx - 16] ^ words[widx - 28] ^ words[widx - 32],
-                    2,
-                );
-            }
+        let len = self.len;
+        self.update(&tail[..tailbytes]);
+        self.len = len;
+    }
 
-            let mut a = self.state[0];
-            let mut b = self.state[1];
-            let mut c = self.state[2];
-            let mut d = self.state[3];
-            let mut e = self.state[4];
-            let mut f = 0;
-            let mut k = 0;
-
-            for (widx, word) in words.iter().enumerate() {
-                if widx < 20 {
-                    f = (b & c) | ((!b) & d);
-                    k = K0;
-                } else if widx < 40 {
-                    f = b ^ c ^ d;
-                    k = K1;
-                } else if widx < 60 {
-                    f = (b & c) | (b & d) | (c & d);
-                    k = K2;
-                } else if widx < 80 {
-                    f = b ^ c ^ d;
-                    k = K3;
-                }
-                let mut temp: u32 = 0;
-                temp = temp
-                    .wrapping_add(__rotate_left_u32(a, 5))
-                    .wrapping_add(f)
-                    .wrapping_add(e)
-                    .wrapping_add(k)
-                    .wrapping_add(*word);
-                e = d;
-                d = c;
-                c = __rotate_left_u32(b, 30);
-                b = a;
-                a = temp;
-            }
+    fn __process_block(&mut self, block: &[u8; 64]) {
+        let mut words = [0u32; 80];
+
+        for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+            *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        //* extend u32*16 into u32*80
+        //* Extend the sixteen 32-bit words into eighty 32-bit words, with potential optimization from:
+        //* "Improving the Performance of the Secure Hash Algorithm (SHA-1)" by Max Locktyukhin
+        for widx in 16..32 {
+            words[widx] = __rotate_left_u32(
+                words[widx - 3] ^ words[widx - 8] ^ words[widx - 14] ^ words[widx - 16],
+                1,
+            );
+        }
+        for widx in 32..80 {
+            words[widx] = __rotate_left_u32(
+                words[widx - 6] ^ words[widx - 16] ^ words[widx - 28] ^ words[widx - 32],
+                2,
+            );
+        }
+
+        let mut a = self.state[0];
+        let mut b = self.state[1];
+        let mut c = self.state[2];
+        let mut d = self.state[3];
+        let mut e = self.state[4];
+        let mut f = 0;
+        let mut k = 0;
 
-            self.state[0] = self.state[0].wrapping_add(a);
-            self.state[1] = self.state[1].wrapping_add(b);
-            self.state[2] = self.state[2].wrapping_add(c);
-            self.state[3] = self.state[3].wrapping_add(d);
-            self.state[4] = self.state[4].wrapping_add(e);
+        for (widx, word) in words.iter().enumerate() {
+            if widx < 20 {
+                f = (b & c) | ((!b) & d);
+                k = K0;
+            } else if widx < 40 {
+                f = b ^ c ^ d;
+                k = K1;
+            } else if widx < 60 {
+                f = (b & c) | (b & d) | (c & d);
+                k = K2;
+            } else if widx < 80 {
+                f = b ^ c ^ d;
+                k = K3;
+            }
+            let mut temp: u32 = 0;
+            temp = temp
+                .wrapping_add(__rotate_left_u32(a, 5))
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(*word);
+            e = d;
+            d = c;
+            c = __rotate_left_u32(b, 30);
+            b = a;
+            a = temp;
         }
+
+        self.state[0] = self.state[0].wrapping_add(a);
+        self.state[1] = self.state[1].wrapping_add(b);
+        self.state[2] = self.state[2].wrapping_add(c);
+        self.state[3] = self.state[3].wrapping_add(d);
+        self.state[4] = self.state[4].wrapping_add(e);
+    }
+
+    /// digest of everything hashed so far, the hasher can keep going
+    pub fn digest(&self) -> ShaOutput {
+        self.clone().finalize()
     }
 
     // create output (160 bits)
-    pub fn digest(&mut self) -> ShaOutput {
+    fn __output(&self) -> ShaOutput {
         ShaOutput::from([
             (self.state[0] >> 24) as u8,
             (self.state[0] >> 16) as u8,
@@ -184,6 +208,17 @@ impl Sha1 {
     }
 }
 
+/// hash everything written
+impl Write for Sha1 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct ShaOutput {
     state: [u8; 20],
@@ -217,7 +252,7 @@ impl ShaOutput {
 
 #[cfg(test)]
 mod test {
-    use std::println;
+    use std::{io::Write, println};
 
     use super::Sha1;
 
@@ -239,4 +274,55 @@ mod test {
             assert_eq!(expected[idx], output);
         }
     }
+
+    #[test]
+    fn sha1_nist_vectors() {
+        let vectors = [
+            ("", "da39a3ee5e6b4b0d3255bfef95601890afd80709"),
+            ("abc", "a9993e364706816aba3e25717850c26c9cd0d89d"),
+            (
+                "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "84983e441c3bd26ebaae4aa1f95129e5e54670f1",
+            ),
+            (
+                "abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                "a49b2446a02c645bf419f995b67091253a04a259",
+            ),
+        ];
+        for (text, expected) in vectors {
+            assert_eq!(Sha1::from(text).finalize().to_hexstring(), expected);
+
+            // byte by byte
+            let mut sha = Sha1::new();
+            for byte in text.as_bytes() {
+                sha.update(&[*byte]);
+            }
+            assert_eq!(sha.digest().to_hexstring(), expected);
+        }
+
+        // one million 'a' written in uneven chunks
+        let mut sha = Sha1::new();
+        let chunk = [b'a'; 997];
+        let mut left = 1_000_000;
+        while left > 0 {
+            let len = left.min(chunk.len());
+            sha.write_all(&chunk[..len]).unwrap();
+            left -= len;
+        }
+        assert_eq!(
+            sha.finalize().to_hexstring(),
+            "34aa973cd4c4daa4f61eeb2bdbad27316534016f"
+        );
+
+        let mut sha = Sha1::from("abc");
+        sha.reset();
+        sha.update(b"ab");
+        let partial = sha.digest();
+        sha.update(b"c");
+        assert_ne!(partial.as_byte(), sha.digest().as_byte());
+        assert_eq!(
+            sha.digest().to_hexstring(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
 }
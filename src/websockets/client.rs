@@ -1,4 +1,10 @@
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+
+use super::{
+    server::WebsocketConnection,
+    util::{derive_accept_key, generate_key},
+};
+use crate::http::{reader::HeadReader, status::StatusCode};
 
 #[derive(Debug)]
 pub enum ConnectionState {
@@ -16,6 +22,10 @@ pub struct Client<Stream> {
     pub max_payload_size: usize,
 
     pub state: ConnectionState,
+    /// `Host` of the upgrade request
+    host: String,
+    /// requested resource
+    path: String,
 }
 
 impl<Stream> Client<Stream> {
@@ -23,48 +33,83 @@ impl<Stream> Client<Stream> {
     /// `stream` : Abstraction represents data stream
     /// `max_size` : max size of payload (default : 16 MB)
     pub fn new(stream: Stream, max_size: Option<usize>) -> Self {
-        match max_size {
-            Some(size) => Self {
-                stream,
-                max_payload_size: size,
-                state: ConnectionState::NeedHandShake,
-            },
-            None => Self {
-                stream,
-                max_payload_size: 16 * 1024 * 1024,
-                state: ConnectionState::NeedHandShake,
-            },
+        Self {
+            stream,
+            max_payload_size: max_size.unwrap_or(16 * 1024 * 1024),
+            state: ConnectionState::NeedHandShake,
+            host: String::from("127.0.0.1:8001"),
+            path: String::from("/"),
         }
     }
+    /// `Host` sent with the upgrade request (default : 127.0.0.1:8001)
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = String::from(host);
+        self
+    }
+    /// resource requested (default : /)
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = String::from(path);
+        self
+    }
 }
 
 impl<Stream> Client<Stream>
 where
     Stream: Unpin + Read + Write,
 {
-    /// handshake with sever
-    fn handshake(mut self) {
-        let header = String::from(
-            "
-GET / HTTP/1.1\r\n
-Host: 127.0.0.1:8001\r\n
-Upgrade: websocket\r\n
-Connection: Upgrade\r\n
-Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n
-Sec-WebSocket-Version: 13\r\n
-",
+    /// handshake with sever, returns the received frames not decoded yet
+    fn handshake(&mut self) -> io::Result<Vec<u8>> {
+        self.state = ConnectionState::MidHandShake;
+        let key = generate_key();
+        let header = format!(
+            "GET {} HTTP/1.1\r\n\
+             Host: {}\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: {}\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             \r\n",
+            self.path, self.host, key
         );
 
-        self.stream.write(header.as_bytes());
-        self.stream.flush();
+        self.stream.write_all(header.as_bytes())?;
+        self.stream.flush()?;
+
+        let (res, rest) = HeadReader::default().read_response(&mut self.stream)?;
+        let expected_accept = derive_accept_key(key.as_bytes());
+        let rejected = if res.status() != Some(StatusCode::SWITCHING_PROTOCOLS) {
+            Some(res.format())
+        } else if !res
+            .get("Upgrade")
+            .is_some_and(|val| val.eq_ignore_ascii_case("websocket"))
+        {
+            Some(String::from("missing Upgrade: websocket"))
+        } else if res.get("Sec-WebSocket-Accept") != Some(&expected_accept) {
+            Some(String::from("wrong Sec-WebSocket-Accept"))
+        } else {
+            None
+        };
+        if let Some(reason) = rejected {
+            self.state = ConnectionState::Close;
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("websocket upgrade rejected : {reason}"),
+            ));
+        }
+        self.state = ConnectionState::Accepted;
+        Ok(rest)
     }
 
-    /// connect with sever
-    pub async fn connect(self) {
-        self.handshake()
+    /// connect with sever : the returned connection masks the frames it sends
+    pub fn connect(mut self) -> io::Result<WebsocketConnection<Stream>> {
+        let rest = self.handshake()?;
+        let mut wc = WebsocketConnection::client(self.stream, Some(self.max_payload_size));
+        wc.connection.connect();
+        wc.feed(&rest);
+        Ok(wc)
     }
     /// send msg to client
-    pub async fn send(&self) {}
+    pub fn send(&self) {}
     /// close connection
-    pub async fn close(&self) {}
+    pub fn close(&self) {}
 }
@@ -0,0 +1,844 @@
+//! offline conformance suite, modelled on the Autobahn TestSuite categories
+//!
+//! every case connects a tester and the implementation under test over TCP
+//! loopback. The tester writes raw frames (masked or not, reserved bits
+//! and opcodes, fragments, ...), the implementation echoes text and binary
+//! messages, and the tester checks the echoes and the close code the
+//! implementation answers with
+
+use std::{
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{
+    client::Client,
+    frame::Frame,
+    message::{CloseFrame, Message, MessageReader, Role},
+    server::WebsocketConnection,
+};
+
+/// message size limit of the implementation under test
+pub const MAX_MESSAGE_SIZE: usize = 70_000;
+/// time the tester waits for each answer
+const ANSWER_TIMEOUT: Duration = Duration::from_secs(5);
+/// time the implementation drains the connection after closing
+const LINGER: Duration = Duration::from_secs(1);
+
+const CONTINUE: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xa;
+
+/// side of the connection under test
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Target {
+    /// `WebsocketConnection` accepting the tester
+    Server,
+    /// `Client` connecting to the tester
+    Client,
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Target::Server => write!(f, "server"),
+            Target::Client => write!(f, "client"),
+        }
+    }
+}
+
+/// frame written as is by the tester
+#[derive(Debug, Clone)]
+struct RawFrame {
+    fin: bool,
+    /// RSV1 to RSV3, as the 3 low bits
+    rsv: u8,
+    /// any 4 bits value, reserved ones included
+    opcode: u8,
+    payload: Vec<u8>,
+    /// mask when the target expects unmasked frames and the other way round
+    flip_mask: bool,
+}
+
+impl RawFrame {
+    fn new(opcode: u8, payload: impl Into<Vec<u8>>) -> Self {
+        RawFrame {
+            fin: true,
+            rsv: 0,
+            opcode,
+            payload: payload.into(),
+            flip_mask: false,
+        }
+    }
+    fn close(code: u16, reason: &str) -> Self {
+        Self::new(CLOSE, CloseFrame::new(code, reason).payload())
+    }
+    /// not the last fragment
+    fn more(mut self) -> Self {
+        self.fin = false;
+        self
+    }
+    fn rsv(mut self, bits: u8) -> Self {
+        self.rsv = bits;
+        self
+    }
+    fn flip_mask(mut self) -> Self {
+        self.flip_mask = true;
+        self
+    }
+    fn encode(&self, mask: bool) -> Vec<u8> {
+        let mut frame = Frame::create_binary_frame(self.payload.clone());
+        frame.header.fin = self.fin;
+        frame.header.rsv1 = self.rsv & 0b100 != 0;
+        frame.header.rsv2 = self.rsv & 0b010 != 0;
+        frame.header.rsv3 = self.rsv & 0b001 != 0;
+        if mask != self.flip_mask {
+            frame.header.set_random_mask();
+        }
+        let mut raw = Vec::new();
+        frame.format(&mut raw).unwrap();
+        raw[0] = (raw[0] & 0xf0) | self.opcode;
+        raw
+    }
+}
+
+/// answer of the implementation under test
+#[derive(Debug, Clone)]
+enum Expect {
+    /// these messages, then a normal close when the tester closes
+    Echo(Vec<Message>),
+    /// a close frame with this code (echoes before it are ignored)
+    Close(Option<u16>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub id: &'static str,
+    pub category: &'static str,
+    pub description: &'static str,
+    frames: Vec<RawFrame>,
+    expect: Expect,
+}
+
+fn case(
+    id: &'static str,
+    category: &'static str,
+    description: &'static str,
+    frames: Vec<RawFrame>,
+    expect: Expect,
+) -> Case {
+    Case {
+        id,
+        category,
+        description,
+        frames,
+        expect,
+    }
+}
+
+fn text(text: &str) -> Message {
+    Message::Text(String::from(text))
+}
+
+/// every case of the suite
+pub fn cases() -> Vec<Case> {
+    use Expect::{Close, Echo};
+
+    let long = |len: usize| "*".repeat(len);
+    let bytes = |len: usize| vec![0xfe; len];
+    vec![
+        // framing
+        case(
+            "1.1.1",
+            "framing",
+            "empty text",
+            vec![RawFrame::new(TEXT, "")],
+            Echo(vec![text("")]),
+        ),
+        case(
+            "1.1.2",
+            "framing",
+            "text, 125 bytes",
+            vec![RawFrame::new(TEXT, long(125))],
+            Echo(vec![text(&long(125))]),
+        ),
+        case(
+            "1.1.3",
+            "framing",
+            "text, 126 bytes (16 bits length)",
+            vec![RawFrame::new(TEXT, long(126))],
+            Echo(vec![text(&long(126))]),
+        ),
+        case(
+            "1.1.4",
+            "framing",
+            "text, 65535 bytes",
+            vec![RawFrame::new(TEXT, long(65535))],
+            Echo(vec![text(&long(65535))]),
+        ),
+        case(
+            "1.1.5",
+            "framing",
+            "text, 65536 bytes (64 bits length)",
+            vec![RawFrame::new(TEXT, long(65536))],
+            Echo(vec![text(&long(65536))]),
+        ),
+        case(
+            "1.2.1",
+            "framing",
+            "empty binary",
+            vec![RawFrame::new(BINARY, "")],
+            Echo(vec![Message::Binary(Vec::new())]),
+        ),
+        case(
+            "1.2.2",
+            "framing",
+            "binary, 65536 bytes",
+            vec![RawFrame::new(BINARY, bytes(65536))],
+            Echo(vec![Message::Binary(bytes(65536))]),
+        ),
+        case(
+            "1.3.1",
+            "framing",
+            "several messages in a row",
+            vec![
+                RawFrame::new(TEXT, "a"),
+                RawFrame::new(BINARY, "b"),
+                RawFrame::new(TEXT, "c"),
+            ],
+            Echo(vec![text("a"), Message::Binary(b"b".to_vec()), text("c")]),
+        ),
+        case(
+            "1.4.1",
+            "framing",
+            "frame masked the wrong way",
+            vec![RawFrame::new(TEXT, "x").flip_mask()],
+            Close(Some(1002)),
+        ),
+        // pings
+        case(
+            "2.1",
+            "pings",
+            "empty ping",
+            vec![RawFrame::new(PING, "")],
+            Echo(vec![Message::Pong(Vec::new())]),
+        ),
+        case(
+            "2.2",
+            "pings",
+            "ping, 125 bytes",
+            vec![RawFrame::new(PING, bytes(125))],
+            Echo(vec![Message::Pong(bytes(125))]),
+        ),
+        case(
+            "2.3",
+            "pings",
+            "ping, 126 bytes",
+            vec![RawFrame::new(PING, bytes(126))],
+            Close(Some(1002)),
+        ),
+        case(
+            "2.4",
+            "pings",
+            "fragmented ping",
+            vec![
+                RawFrame::new(PING, "a").more(),
+                RawFrame::new(CONTINUE, "b"),
+            ],
+            Close(Some(1002)),
+        ),
+        case(
+            "2.5",
+            "pings",
+            "unsolicited pong is ignored",
+            vec![RawFrame::new(PONG, "p"), RawFrame::new(TEXT, "t")],
+            Echo(vec![text("t")]),
+        ),
+        case(
+            "2.6",
+            "pings",
+            "ten pings",
+            (0..10).map(|idx| RawFrame::new(PING, vec![idx])).collect(),
+            Echo((0..10).map(|idx| Message::Pong(vec![idx])).collect()),
+        ),
+        // reserved bits
+        case(
+            "3.1",
+            "reserved bits",
+            "text with RSV1",
+            vec![RawFrame::new(TEXT, "x").rsv(0b100)],
+            Close(Some(1002)),
+        ),
+        case(
+            "3.2",
+            "reserved bits",
+            "text with RSV2",
+            vec![RawFrame::new(TEXT, "x").rsv(0b010)],
+            Close(Some(1002)),
+        ),
+        case(
+            "3.3",
+            "reserved bits",
+            "text with RSV3",
+            vec![RawFrame::new(TEXT, "x").rsv(0b001)],
+            Close(Some(1002)),
+        ),
+        case(
+            "3.4",
+            "reserved bits",
+            "ping with all RSV bits",
+            vec![RawFrame::new(PING, "").rsv(0b111)],
+            Close(Some(1002)),
+        ),
+        case(
+            "3.5",
+            "reserved bits",
+            "valid text, then text with RSV2",
+            vec![
+                RawFrame::new(TEXT, "ok"),
+                RawFrame::new(TEXT, "x").rsv(0b010),
+            ],
+            Close(Some(1002)),
+        ),
+        // opcodes
+        case(
+            "4.1.1",
+            "opcodes",
+            "reserved data opcode 3",
+            vec![RawFrame::new(0x3, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "4.1.2",
+            "opcodes",
+            "reserved data opcode 7",
+            vec![RawFrame::new(0x7, "x")],
+            Close(Some(1002)),
+        ),
+        case(
+            "4.2.1",
+            "opcodes",
+            "reserved control opcode 11",
+            vec![RawFrame::new(0xb, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "4.2.2",
+            "opcodes",
+            "reserved control opcode 15",
+            vec![RawFrame::new(0xf, "x")],
+            Close(Some(1002)),
+        ),
+        case(
+            "4.3",
+            "opcodes",
+            "valid text, then reserved opcode",
+            vec![RawFrame::new(TEXT, "ok"), RawFrame::new(0x5, "")],
+            Close(Some(1002)),
+        ),
+        // fragmentation
+        case(
+            "5.1",
+            "fragmentation",
+            "text in two fragments",
+            vec![
+                RawFrame::new(TEXT, "frag").more(),
+                RawFrame::new(CONTINUE, "ment"),
+            ],
+            Echo(vec![text("fragment")]),
+        ),
+        case(
+            "5.2",
+            "fragmentation",
+            "binary in three fragments",
+            vec![
+                RawFrame::new(BINARY, "a").more(),
+                RawFrame::new(CONTINUE, "b").more(),
+                RawFrame::new(CONTINUE, "c"),
+            ],
+            Echo(vec![Message::Binary(b"abc".to_vec())]),
+        ),
+        case(
+            "5.3",
+            "fragmentation",
+            "ping between fragments",
+            vec![
+                RawFrame::new(TEXT, "a").more(),
+                RawFrame::new(PING, "p"),
+                RawFrame::new(CONTINUE, "b"),
+            ],
+            Echo(vec![Message::Pong(b"p".to_vec()), text("ab")]),
+        ),
+        case(
+            "5.4",
+            "fragmentation",
+            "empty fragments",
+            vec![
+                RawFrame::new(TEXT, "").more(),
+                RawFrame::new(CONTINUE, "").more(),
+                RawFrame::new(CONTINUE, "x"),
+            ],
+            Echo(vec![text("x")]),
+        ),
+        case(
+            "5.5",
+            "fragmentation",
+            "continuation without message",
+            vec![RawFrame::new(CONTINUE, "x")],
+            Close(Some(1002)),
+        ),
+        case(
+            "5.6",
+            "fragmentation",
+            "new text before the last fragment",
+            vec![RawFrame::new(TEXT, "a").more(), RawFrame::new(TEXT, "b")],
+            Close(Some(1002)),
+        ),
+        case(
+            "5.7",
+            "fragmentation",
+            "fragmented close",
+            vec![
+                RawFrame::close(1000, "").more(),
+                RawFrame::new(CONTINUE, ""),
+            ],
+            Close(Some(1002)),
+        ),
+        // UTF-8 handling
+        case(
+            "6.1",
+            "utf-8",
+            "valid multi-byte text",
+            vec![RawFrame::new(TEXT, "κόσμε ✓ 𝄞")],
+            Echo(vec![text("κόσμε ✓ 𝄞")]),
+        ),
+        case(
+            "6.2",
+            "utf-8",
+            "code point split between fragments",
+            vec![
+                RawFrame::new(TEXT, &b"\xf0\x9d"[..]).more(),
+                RawFrame::new(CONTINUE, &b"\x84\x9e"[..]),
+            ],
+            Echo(vec![text("𝄞")]),
+        ),
+        case(
+            "6.3",
+            "utf-8",
+            "invalid byte",
+            vec![RawFrame::new(TEXT, &b"ab\xff"[..])],
+            Close(Some(1007)),
+        ),
+        case(
+            "6.4",
+            "utf-8",
+            "overlong encoding",
+            vec![RawFrame::new(TEXT, &b"\xc0\xaf"[..])],
+            Close(Some(1007)),
+        ),
+        case(
+            "6.5",
+            "utf-8",
+            "surrogate code point",
+            vec![RawFrame::new(TEXT, &b"\xed\xa0\x80"[..])],
+            Close(Some(1007)),
+        ),
+        case(
+            "6.6",
+            "utf-8",
+            "truncated sequence",
+            vec![RawFrame::new(TEXT, &b"\xe2\x82"[..])],
+            Close(Some(1007)),
+        ),
+        case(
+            "6.7",
+            "utf-8",
+            "invalid sequence split between fragments",
+            vec![
+                RawFrame::new(TEXT, &b"a\xc3"[..]).more(),
+                RawFrame::new(CONTINUE, "("),
+            ],
+            Close(Some(1007)),
+        ),
+        // close codes
+        case(
+            "7.1.1",
+            "close codes",
+            "empty close",
+            vec![RawFrame::new(CLOSE, "")],
+            Close(None),
+        ),
+        case(
+            "7.1.2",
+            "close codes",
+            "close 1000 with reason",
+            vec![RawFrame::close(1000, "bye")],
+            Close(Some(1000)),
+        ),
+        case(
+            "7.1.3",
+            "close codes",
+            "close 1 byte payload",
+            vec![RawFrame::new(CLOSE, &b"\x03"[..])],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.1.4",
+            "close codes",
+            "close 125 bytes payload",
+            vec![RawFrame::close(1000, &long(123))],
+            Close(Some(1000)),
+        ),
+        case(
+            "7.1.5",
+            "close codes",
+            "close 126 bytes payload",
+            vec![RawFrame::close(1000, &long(124))],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.2.1",
+            "close codes",
+            "close 1001",
+            vec![RawFrame::close(1001, "")],
+            Close(Some(1001)),
+        ),
+        case(
+            "7.2.2",
+            "close codes",
+            "close 1011",
+            vec![RawFrame::close(1011, "")],
+            Close(Some(1011)),
+        ),
+        case(
+            "7.2.3",
+            "close codes",
+            "close 3000 (registered)",
+            vec![RawFrame::close(3000, "")],
+            Close(Some(3000)),
+        ),
+        case(
+            "7.2.4",
+            "close codes",
+            "close 4999 (private)",
+            vec![RawFrame::close(4999, "")],
+            Close(Some(4999)),
+        ),
+        case(
+            "7.3.1",
+            "close codes",
+            "close 999",
+            vec![RawFrame::close(999, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.3.2",
+            "close codes",
+            "close 1004 (reserved)",
+            vec![RawFrame::close(1004, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.3.3",
+            "close codes",
+            "close 1005 (not sent)",
+            vec![RawFrame::close(1005, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.3.4",
+            "close codes",
+            "close 1006 (not sent)",
+            vec![RawFrame::close(1006, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.3.5",
+            "close codes",
+            "close 1015 (not sent)",
+            vec![RawFrame::close(1015, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.3.6",
+            "close codes",
+            "close 5000",
+            vec![RawFrame::close(5000, "")],
+            Close(Some(1002)),
+        ),
+        case(
+            "7.4.1",
+            "close codes",
+            "close reason not utf-8",
+            vec![RawFrame::new(CLOSE, &b"\x03\xe8\xff"[..])],
+            Close(Some(1007)),
+        ),
+        case(
+            "7.5.1",
+            "close codes",
+            "text after close is ignored",
+            vec![RawFrame::close(1000, ""), RawFrame::new(TEXT, "late")],
+            Close(Some(1000)),
+        ),
+        // limits
+        case(
+            "9.1.1",
+            "limits",
+            "text at the size limit",
+            vec![RawFrame::new(TEXT, long(MAX_MESSAGE_SIZE))],
+            Echo(vec![text(&long(MAX_MESSAGE_SIZE))]),
+        ),
+        case(
+            "9.1.2",
+            "limits",
+            "binary over the size limit",
+            vec![RawFrame::new(BINARY, bytes(MAX_MESSAGE_SIZE + 1))],
+            Close(Some(1009)),
+        ),
+        case(
+            "9.1.3",
+            "limits",
+            "fragments over the size limit",
+            vec![
+                RawFrame::new(BINARY, bytes(30_000)).more(),
+                RawFrame::new(CONTINUE, bytes(30_000)).more(),
+                RawFrame::new(CONTINUE, bytes(30_000)),
+            ],
+            Close(Some(1009)),
+        ),
+        case(
+            "9.1.4",
+            "limits",
+            "many small fragments",
+            {
+                let mut frames: Vec<_> = (0..100)
+                    .map(|_| RawFrame::new(CONTINUE, "ab").more())
+                    .collect();
+                frames[0].opcode = TEXT;
+                frames.push(RawFrame::new(CONTINUE, ""));
+                frames
+            },
+            Echo(vec![text(&"ab".repeat(100))]),
+        ),
+    ]
+}
+
+/// result of a case against one target
+#[derive(Debug)]
+pub struct Outcome {
+    pub target: Target,
+    pub id: &'static str,
+    pub category: &'static str,
+    pub description: &'static str,
+    /// why the case failed
+    pub result: Result<(), String>,
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub outcomes: Vec<Outcome>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.outcomes.iter().filter(|o| o.result.is_ok()).count()
+    }
+    pub fn failed(&self) -> Vec<&Outcome> {
+        self.outcomes.iter().filter(|o| o.result.is_err()).collect()
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for outcome in &self.outcomes {
+            let status = if outcome.result.is_ok() {
+                "PASS"
+            } else {
+                "FAIL"
+            };
+            writeln!(
+                f,
+                "{status} {:<6} {:<6} {:<14} {}",
+                outcome.target, outcome.id, outcome.category, outcome.description
+            )?;
+            if let Err(reason) = &outcome.result {
+                writeln!(f, "     -> {reason}")?;
+            }
+        }
+        write!(f, "{}/{} cases passed", self.passed(), self.outcomes.len())
+    }
+}
+
+/// run every case against both targets
+pub fn run_all() -> Report {
+    let mut report = run(Target::Server);
+    report.outcomes.extend(run(Target::Client).outcomes);
+    report
+}
+
+/// run every case against `target`
+pub fn run(target: Target) -> Report {
+    let outcomes = cases()
+        .into_iter()
+        .map(|case| Outcome {
+            target,
+            id: case.id,
+            category: case.category,
+            description: case.description,
+            result: run_case(target, &case),
+        })
+        .collect();
+    Report { outcomes }
+}
+
+/// end of the connection driven by the suite
+struct Tester {
+    wc: WebsocketConnection<TcpStream>,
+    /// validates the frames of the target
+    reader: MessageReader,
+    mask: bool,
+}
+
+impl Tester {
+    fn new(wc: WebsocketConnection<TcpStream>, role: Role) -> io::Result<Self> {
+        wc.stream.set_read_timeout(Some(ANSWER_TIMEOUT))?;
+        Ok(Tester {
+            wc,
+            reader: MessageReader::new(role, usize::MAX),
+            mask: role == Role::Client,
+        })
+    }
+    fn send(&mut self, frame: &RawFrame) {
+        // the target may already have failed the connection
+        let _ = self.wc.stream.write_all(&frame.encode(self.mask));
+    }
+    fn next_message(&mut self) -> Result<Message, String> {
+        loop {
+            let frame = self
+                .wc
+                .try_receive()
+                .map_err(|err| format!("no answer ({err})"))?;
+            match self.reader.push(frame) {
+                Ok(Some(message)) => return Ok(message),
+                Ok(None) => continue,
+                Err(close) => return Err(format!("invalid frame from target ({close})")),
+            }
+        }
+    }
+    fn check(&mut self, expect: &Expect) -> Result<(), String> {
+        match expect {
+            Expect::Echo(messages) => {
+                for expected in messages {
+                    let got = self.next_message()?;
+                    if got != *expected {
+                        return Err(format!("expected {expected}, got {got}"));
+                    }
+                }
+                self.send(&RawFrame::close(1000, ""));
+                match self.next_message()? {
+                    Message::Close(Some(close)) if close.code == 1000 => Ok(()),
+                    got => Err(format!("expected close 1000, got {got}")),
+                }
+            }
+            Expect::Close(code) => loop {
+                if let Message::Close(close) = self.next_message()? {
+                    let got = close.map(|close| close.code);
+                    if got == *code {
+                        return Ok(());
+                    }
+                    return Err(format!("expected close code {code:?}, got {got:?}"));
+                }
+            },
+        }
+    }
+}
+
+/// echo text and binary messages until the connection is closed
+fn echo(mut wc: WebsocketConnection<TcpStream>) {
+    loop {
+        let answer = match wc.read_message() {
+            Ok(Message::Text(text)) => Message::Text(text),
+            Ok(Message::Binary(data)) => Message::Binary(data),
+            Ok(Message::Close(_)) | Err(_) => break,
+            Ok(_) => continue,
+        };
+        if wc.send_message(answer).is_err() {
+            break;
+        }
+    }
+    // drain what the tester still sends, unread bytes would reset the
+    // connection before the close frame is read
+    let _ = wc.stream.shutdown(Shutdown::Write);
+    let _ = wc.stream.set_read_timeout(Some(LINGER));
+    let mut sink = [0u8; 4096];
+    while matches!(wc.stream.read(&mut sink), Ok(len) if len > 0) {}
+}
+
+/// connect the tester and the target, which echoes in its own thread
+fn connect(target: Target) -> io::Result<(Tester, JoinHandle<io::Result<()>>)> {
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+
+    match target {
+        Target::Server => {
+            let peer = thread::spawn(move || {
+                let (stream, _) = listener.accept()?;
+                let mut wc = WebsocketConnection::new(stream, Some(MAX_MESSAGE_SIZE));
+                wc.handshake()?;
+                echo(wc);
+                Ok(())
+            });
+            let wc = Client::new(TcpStream::connect(addr)?, None)
+                .host(&addr.to_string())
+                .connect()?;
+            Ok((Tester::new(wc, Role::Client)?, peer))
+        }
+        Target::Client => {
+            let peer = thread::spawn(move || {
+                let wc = Client::new(TcpStream::connect(addr)?, Some(MAX_MESSAGE_SIZE))
+                    .host(&addr.to_string())
+                    .connect()?;
+                echo(wc);
+                Ok(())
+            });
+            let (stream, _) = listener.accept()?;
+            let mut wc = WebsocketConnection::new(stream, None);
+            wc.handshake()?;
+            Ok((Tester::new(wc, Role::Server)?, peer))
+        }
+    }
+}
+
+fn run_case(target: Target, case: &Case) -> Result<(), String> {
+    let (mut tester, peer) = connect(target).map_err(|err| format!("handshake failed ({err})"))?;
+    for frame in &case.frames {
+        tester.send(frame);
+    }
+    let result = tester.check(&case.expect);
+    drop(tester);
+
+    match peer.join() {
+        Ok(Ok(())) => result,
+        Ok(Err(err)) => result.and(Err(format!("target failed ({err})"))),
+        Err(_) => Err(String::from("target panicked")),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn conformance_suite() {
+        let report = run_all();
+        println!("{report}");
+        assert_eq!(report.outcomes.len(), 2 * cases().len());
+        assert!(report.failed().is_empty(), "{report}");
+    }
+}
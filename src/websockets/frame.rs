@@ -1,9 +1,6 @@
 use std::{
-    any::Any,
-    eprintln,
-    fmt::{format, Display},
-    io::{BufRead, Bytes, Cursor, Read, Write},
-    println, vec,
+    fmt::Display,
+    io::{Cursor, Read, Write},
 };
 
 //  Data frame spec from RFC6455
@@ -64,7 +61,8 @@ impl Opcode {
             _ => Opcode::Reserved,
         }
     }
-    fn format(&self) -> u8 {
+    /// opcode bits of the first header byte
+    pub fn format(&self) -> u8 {
         match self {
             Opcode::Data(Data::Continue) => 0b0000,
             Opcode::Data(Data::Text) => 0b0001,
@@ -188,7 +186,7 @@ Header
                     "
 <Payload>
 {}",
-                    String::from_utf8(self.payload.clone()).unwrap()
+                    String::from_utf8_lossy(&self.payload)
                 )
             }
             _ => {
@@ -206,26 +204,47 @@ Header
 
 impl Frame {
     /// parse bytes to internal Data
-    pub fn parse(raw: &mut Vec<u8>) -> Self {
+    ///
+    /// fails with `UnexpectedEof` when `raw` does not hold the whole frame
+    pub fn parse(raw: &[u8]) -> std::io::Result<Self> {
         let mut frame_cursor = Cursor::new(raw);
-        let frame_header = FrameHeader::parse(&mut frame_cursor).unwrap().unwrap();
-
-        let mut payload = vec![0; frame_header.payloadlength as usize];
-
-        frame_cursor.read_exact(&mut payload).unwrap();
+        let frame_header = FrameHeader::parse(&mut frame_cursor)?.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "incomplete frame header")
+        })?;
+
+        // checked before allocating : the length comes from the peer
+        let start = frame_cursor.position() as usize;
+        if frame_header.payloadlength > (raw.len() - start) as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "incomplete frame payload",
+            ));
+        }
+        let end = start + frame_header.payloadlength as usize;
+        let mut payload = raw[start..end].to_vec();
 
-        Self::applymask(&mut payload, frame_header.mask.unwrap());
+        if let Some(mask) = frame_header.mask {
+            Self::applymask(&mut payload, mask);
+        }
 
-        Frame {
+        Ok(Frame {
             header: frame_header,
             payload,
-        }
+        })
     }
     /// write bytes to output form internal data
+    ///
+    /// payload is masked with the header mask when there is one
     pub fn format(&self, output: &mut impl Write) -> Result<(), std::io::Error> {
-        self.header.format(output).unwrap();
-        output.write_all(self.payload.as_slice()).unwrap();
-        Ok(())
+        self.header.format(output)?;
+        match self.header.mask {
+            Some(mask) => {
+                let mut payload = self.payload.clone();
+                Self::applymask(&mut payload, mask);
+                output.write_all(&payload)
+            }
+            None => output.write_all(self.payload.as_slice()),
+        }
     }
     fn applymask(target: &mut [u8], mask: u32) {
         let b1: u8 = ((mask >> 24) & 0xff) as u8;
@@ -252,25 +271,44 @@ impl Frame {
         let payload = Vec::from(msg.as_bytes());
         Frame { header, payload }
     }
+    pub fn create_binary_frame(data: Vec<u8>) -> Self {
+        let header = FrameHeader {
+            fin: true,
+            rsv1: false,
+            rsv2: false,
+            rsv3: false,
+            opcode: Opcode::Data(Data::Binary),
+            mask: None,
+            masked: false,
+            payloadlength: data.len() as u64,
+        };
+        Frame {
+            header,
+            payload: data,
+        }
+    }
     pub fn create_pong_frame() -> Self {
+        Self::create_control_frame(Control::Pong, Vec::new())
+    }
+    /// control frame, `payload` must not exceed 125 bytes
+    pub fn create_control_frame(control: Control, payload: Vec<u8>) -> Self {
         let header = FrameHeader {
             fin: true,
             rsv1: false,
             rsv2: false,
             rsv3: false,
-            opcode: Opcode::Control(Control::Close),
+            opcode: Opcode::Control(control),
             mask: None,
             masked: false,
-            payloadlength: 0,
+            payloadlength: payload.len() as u64,
         };
-        let payload = Vec::new();
         Frame { header, payload }
     }
 }
 
 impl Display for FrameHeader {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        let mask = self.mask.unwrap();
+        let mask = self.mask.unwrap_or_default();
         write!(
             f,
             "
@@ -285,8 +323,6 @@ impl Display for FrameHeader {
 }
 impl FrameHeader {
     pub fn parse(cursor: &mut Cursor<impl AsRef<[u8]>>) -> Result<Option<Self>, std::io::Error> {
-        let start = cursor.position();
-
         let mut head_buffer = [0u8; 2];
         if cursor.read(&mut head_buffer)? != 2 {
             return Ok(None);
@@ -313,10 +349,15 @@ impl FrameHeader {
                 } else {
                     // extra_bytes == 8
                     let mut buf = [0; 8];
-                    match cursor.read_exact(&mut buf) {
-                        Ok(_) => u64::from_be_bytes(buf),
-                        Err(err) => return Err(err),
+                    cursor.read_exact(&mut buf)?;
+                    // most significant bit must be 0 (RFC 6455 section 5.2)
+                    if buf[0] & 0b1000_0000 != 0 {
+                        return Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "payload length over 63 bits",
+                        ));
                     }
+                    u64::from_be_bytes(buf)
                 }
             } else {
                 u64::from(length_bytes)
@@ -348,7 +389,8 @@ impl FrameHeader {
 
         Ok(Some(header))
     }
-    fn format(&self, output: &mut impl Write) -> Result<(), std::io::Error> {
+    /// write header bytes, shortest payload length encoding
+    pub fn format(&self, output: &mut impl Write) -> Result<(), std::io::Error> {
         let fin = if self.fin { 0b1000_0000 } else { 0 };
         let rsv1 = if self.rsv1 { 0b0100_0000 } else { 0 };
         let rsv2 = if self.rsv2 { 0b0010_0000 } else { 0 };
@@ -363,22 +405,22 @@ impl FrameHeader {
 
         let second = maskflag | lengthflag;
 
-        output.write(&[first, second]);
+        output.write_all(&[first, second])?;
 
         match length {
             PayloadLength::U8(_) => (),
             PayloadLength::U16 => {
                 let buf = (self.payloadlength as u16).to_be_bytes();
-                output.write(&buf);
+                output.write_all(&buf)?;
             }
             PayloadLength::U64 => {
                 let buf = (self.payloadlength).to_be_bytes();
-                output.write(&buf);
+                output.write_all(&buf)?;
             }
         };
 
-        if self.mask.is_some() {
-            output.write(self.mask.unwrap().to_be_bytes().as_ref());
+        if let Some(mask) = self.mask {
+            output.write_all(mask.to_be_bytes().as_ref())?;
         }
 
         Ok(())
@@ -387,7 +429,8 @@ impl FrameHeader {
     fn get_random_mask(&self) -> u32 {
         rand::random()
     }
-    fn set_random_mask(&mut self) {
+    /// mask the frame with random key (client to server frames must be masked)
+    pub fn set_random_mask(&mut self) {
         self.mask = Some(self.get_random_mask());
         self.masked = true;
     }
@@ -395,12 +438,7 @@ impl FrameHeader {
 
 #[cfg(test)]
 mod test {
-    use std::{
-        io::{Cursor, Read},
-        print, println,
-    };
-
-    use crate::websockets::frame::Opcode;
+    use std::{io::Read, print, println};
 
     use super::FrameHeader;
 
@@ -419,7 +457,7 @@ mod test {
         header.set_random_mask();
         println!("Header: {}", header);
         let mut formatted = Vec::new();
-        header.format(&mut formatted);
+        header.format(&mut formatted).unwrap();
 
         for bytes in formatted.bytes() {
             let bt = bytes.unwrap();
@@ -472,7 +510,7 @@ mod test {
                     header.set_random_mask();
                     println!("Header: {}", header);
                     let mut formatted = Vec::new();
-                    header.format(&mut formatted);
+                    header.format(&mut formatted).unwrap();
 
                     for bytes in formatted.bytes() {
                         let bt = bytes.unwrap();
@@ -488,4 +526,167 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn parse_truncated_and_oversized() {
+        use super::*;
+        use std::io::ErrorKind;
+
+        let raw = b"\x81\x82\x01\x02\x03\x04\x69\x6b";
+        assert_eq!(Frame::parse(raw).unwrap().payload, b"hi");
+        for len in 0..raw.len() {
+            assert_eq!(
+                Frame::parse(&raw[..len]).unwrap_err().kind(),
+                ErrorKind::UnexpectedEof
+            );
+        }
+
+        // announced length is not allocated before the payload is there
+        let huge = b"\x82\x7f\x7f\xff\xff\xff\xff\xff\xff\xff";
+        assert_eq!(
+            Frame::parse(huge).unwrap_err().kind(),
+            ErrorKind::UnexpectedEof
+        );
+        let msb = b"\x82\x7f\x80\0\0\0\0\0\0\0";
+        assert_eq!(
+            Frame::parse(msb).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+    }
+
+    mod roundtrip {
+        use proptest::prelude::*;
+
+        use super::super::*;
+        use crate::{net::pipe::duplex, websockets::server::WebsocketConnection};
+
+        /// lengths around the 7 bits, 16 bits and 64 bits encodings
+        fn payload_length() -> impl Strategy<Value = u64> {
+            prop_oneof![
+                0..=130u64,
+                65530..=65540u64,
+                (0u64..1 << 63),
+                Just((1u64 << 63) - 1),
+            ]
+        }
+
+        fn opcode() -> impl Strategy<Value = Opcode> {
+            prop::sample::select(vec![
+                Opcode::Data(Data::Continue),
+                Opcode::Data(Data::Text),
+                Opcode::Data(Data::Binary),
+                Opcode::Control(Control::Close),
+                Opcode::Control(Control::Ping),
+                Opcode::Control(Control::Pong),
+                Opcode::Reserved,
+            ])
+        }
+
+        fn header(length: impl Strategy<Value = u64>) -> impl Strategy<Value = FrameHeader> {
+            (any::<[bool; 4]>(), opcode(), length, any::<Option<u32>>()).prop_map(
+                |([fin, rsv1, rsv2, rsv3], opcode, payloadlength, mask)| FrameHeader {
+                    fin,
+                    rsv1,
+                    rsv2,
+                    rsv3,
+                    opcode,
+                    masked: mask.is_some(),
+                    payloadlength,
+                    mask,
+                },
+            )
+        }
+
+        /// payloads at the length boundaries, filled from a seed
+        fn payload() -> impl Strategy<Value = Vec<u8>> {
+            prop_oneof![
+                prop::collection::vec(any::<u8>(), 0..300),
+                (
+                    prop::sample::select(vec![125usize, 126, 127, 65535, 65536, 65537]),
+                    any::<u8>()
+                )
+                    .prop_map(|(len, seed)| (0..len).map(|i| seed ^ i as u8).collect()),
+            ]
+        }
+
+        fn frame() -> impl Strategy<Value = Frame> {
+            (payload(), header(Just(0))).prop_map(|(payload, mut header)| {
+                header.payloadlength = payload.len() as u64;
+                Frame { header, payload }
+            })
+        }
+
+        /// RFC 6455 encoding of the header, written independently of `format`
+        fn expected_header(header: &FrameHeader) -> Vec<u8> {
+            let flags = [header.fin, header.rsv1, header.rsv2, header.rsv3]
+                .iter()
+                .fold(0u8, |acc, bit| acc << 1 | *bit as u8);
+            let mut raw = vec![flags << 4 | header.opcode.format()];
+            let mask_bit = if header.mask.is_some() { 0x80 } else { 0 };
+            match header.payloadlength {
+                len @ 0..=125 => raw.push(mask_bit | len as u8),
+                len @ 126..=0xffff => {
+                    raw.push(mask_bit | 126);
+                    raw.extend_from_slice(&(len as u16).to_be_bytes());
+                }
+                len => {
+                    raw.push(mask_bit | 127);
+                    raw.extend_from_slice(&len.to_be_bytes());
+                }
+            }
+            if let Some(mask) = header.mask {
+                raw.extend_from_slice(&mask.to_be_bytes());
+            }
+            raw
+        }
+
+        proptest! {
+            #[test]
+            fn header_roundtrip(header in header(payload_length())) {
+                let mut raw = Vec::new();
+                header.format(&mut raw).unwrap();
+                prop_assert_eq!(&raw, &expected_header(&header));
+
+                let parsed = FrameHeader::parse(&mut Cursor::new(&raw)).unwrap().unwrap();
+                prop_assert_eq!(parsed, header);
+            }
+
+            #[test]
+            fn frame_roundtrip(frame in frame()) {
+                let mut raw = Vec::new();
+                frame.format(&mut raw).unwrap();
+
+                let mut expected = expected_header(&frame.header);
+                let mask = frame.header.mask.unwrap_or(0).to_be_bytes();
+                expected.extend(frame.payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+                prop_assert_eq!(&raw, &expected);
+
+                prop_assert_eq!(Frame::parse(&raw).unwrap(), frame);
+            }
+        }
+
+        proptest! {
+            #![proptest_config(ProptestConfig::with_cases(32))]
+
+            /// frames cut in arbitrary chunks come out of the streaming
+            /// decoder of `WebsocketConnection` unchanged
+            #[test]
+            fn chunked_delivery(frames in prop::collection::vec(frame(), 1..4), chunk in 1usize..200) {
+                let (server, mut client) = duplex();
+                let mut wc = WebsocketConnection::new(server.read_chunk(chunk), None);
+                for frame in &frames {
+                    frame.format(&mut client).unwrap();
+                }
+                drop(client);
+
+                for frame in frames {
+                    prop_assert_eq!(wc.try_receive().unwrap(), frame);
+                }
+                prop_assert_eq!(
+                    wc.try_receive().unwrap_err().kind(),
+                    std::io::ErrorKind::UnexpectedEof
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,484 @@
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+#[cfg(unix)]
+use crate::net::bind_unix;
+
+use super::{
+    message::{CloseFrame, Message, CLOSE_GOING_AWAY},
+    server::{close_answer, violation, WebsocketConnection},
+};
+use crate::{
+    http::{reader::HeadReader, response::Response, status::StatusCode},
+    net::{accept_failed, Listener, NetStream, ShutdownHandle},
+    worker::ThreadPool,
+};
+
+/// id of a connection, unique for the lifetime of a `Server`
+pub type ConnectionId = u64;
+
+type ConnectCallback<Stream> = Arc<dyn Fn(&Server<Stream>, ConnectionId) + Send + Sync>;
+type MessageCallback<Stream> = Arc<dyn Fn(&Server<Stream>, ConnectionId, Message) + Send + Sync>;
+/// close frame of the peer, `None` when the connection was lost or failed
+type CloseCallback<Stream> =
+    Arc<dyn Fn(&Server<Stream>, ConnectionId, Option<CloseFrame>) + Send + Sync>;
+
+/// connection id -> writer
+type Connections<Stream> =
+    Arc<Mutex<HashMap<ConnectionId, Arc<Mutex<WebsocketConnection<Stream>>>>>>;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub url: String,
+    /// connections served at once, one thread each : connections beyond
+    /// are answered with 503
+    pub threads: usize,
+    pub max_payload_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            url: String::from("127.0.0.1:8001"),
+            threads: 16,
+            max_payload_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// plain WebSocket server : keeps a registry of the open connections,
+/// calls the connect / message / close callbacks and sends to a single
+/// connection or to every one
+///
+/// clones share the registry, so a clone can push messages while `serve` runs
+pub struct Server<Stream> {
+    connections: Connections<Stream>,
+    next_id: Arc<AtomicU64>,
+    config: Config,
+    on_connect: Option<ConnectCallback<Stream>>,
+    on_message: Option<MessageCallback<Stream>>,
+    on_close: Option<CloseCallback<Stream>>,
+    shutdown: ShutdownHandle,
+}
+
+impl<Stream> Clone for Server<Stream> {
+    fn clone(&self) -> Self {
+        Server {
+            connections: self.connections.clone(),
+            next_id: self.next_id.clone(),
+            config: self.config.clone(),
+            on_connect: self.on_connect.clone(),
+            on_message: self.on_message.clone(),
+            on_close: self.on_close.clone(),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+}
+
+impl<Stream> Debug for Server<Stream> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Server")
+            .field("config", &self.config)
+            .field("connections", &self.connections())
+            .finish()
+    }
+}
+
+impl<Stream> Server<Stream> {
+    pub fn new(config: Config) -> Self {
+        Server {
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            next_id: Arc::new(AtomicU64::new(1)),
+            config,
+            on_connect: None,
+            on_message: None,
+            on_close: None,
+            shutdown: ShutdownHandle::default(),
+        }
+    }
+    /// called once the handshake of a connection is done
+    pub fn on_connect<F>(&mut self, callback: F)
+    where
+        F: Fn(&Self, ConnectionId) + Send + Sync + 'static,
+    {
+        self.on_connect = Some(Arc::new(callback));
+    }
+    /// called for every text and binary message (pings are answered)
+    pub fn on_message<F>(&mut self, callback: F)
+    where
+        F: Fn(&Self, ConnectionId, Message) + Send + Sync + 'static,
+    {
+        self.on_message = Some(Arc::new(callback));
+    }
+    /// called once a connection is removed from the registry
+    pub fn on_close<F>(&mut self, callback: F)
+    where
+        F: Fn(&Self, ConnectionId, Option<CloseFrame>) + Send + Sync + 'static,
+    {
+        self.on_close = Some(Arc::new(callback));
+    }
+    /// ids of the open connections, in connection order
+    pub fn connections(&self) -> Vec<ConnectionId> {
+        let mut ids: Vec<ConnectionId> = self.connections.lock().unwrap().keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+    /// handle used to stop `listen` / `serve` from another thread
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    fn writer(&self, id: ConnectionId) -> Option<Arc<Mutex<WebsocketConnection<Stream>>>> {
+        self.connections.lock().unwrap().get(&id).cloned()
+    }
+    /// writers of every open connection, in connection order
+    fn writers(&self) -> Vec<(ConnectionId, Arc<Mutex<WebsocketConnection<Stream>>>)> {
+        let mut writers: Vec<_> = self
+            .connections
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, writer)| (*id, writer.clone()))
+            .collect();
+        writers.sort_unstable_by_key(|(id, _)| *id);
+        writers
+    }
+    fn register(&self, writer: Arc<Mutex<WebsocketConnection<Stream>>>) -> ConnectionId {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        self.connections.lock().unwrap().insert(id, writer);
+        id
+    }
+}
+
+impl<Stream> Server<Stream>
+where
+    Stream: Unpin + Read + Write,
+{
+    /// send `message` to connection `id`
+    pub fn send_to(&self, id: ConnectionId, message: Message) -> io::Result<()> {
+        let writer = self.writer(id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no connection {id}"))
+        })?;
+        let result = writer.lock().unwrap().send_message(message);
+        result
+    }
+    /// send `message` to every open connection
+    ///
+    /// returns the number of connections it was written to
+    pub fn broadcast(&self, message: Message) -> usize {
+        self.writers()
+            .into_iter()
+            .filter(
+                |(id, writer)| match writer.lock().unwrap().send_message(message.clone()) {
+                    Ok(()) => true,
+                    Err(err) => {
+                        println!("ERR>> broadcast to {id} failed : {err}");
+                        false
+                    }
+                },
+            )
+            .count()
+    }
+    /// start the closing handshake of connection `id`, it is removed
+    /// once the peer answers
+    pub fn close(&self, id: ConnectionId, code: u16, reason: &str) -> io::Result<()> {
+        let writer = self.writer(id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("no connection {id}"))
+        })?;
+        let result = writer.lock().unwrap().send_close(code, reason);
+        result
+    }
+}
+
+impl Server<TcpStream> {
+    /// bind `config.url` and serve connections until shutdown
+    pub fn listen(self) -> io::Result<()> {
+        let listener = TcpListener::bind(&self.config.url)?;
+        self.serve(listener)
+    }
+}
+
+#[cfg(unix)]
+impl Server<UnixStream> {
+    /// bind socket file `config.url` and serve connections until shutdown
+    pub fn listen(self) -> io::Result<()> {
+        let listener = bind_unix(&self.config.url)?;
+        self.serve(listener)
+    }
+}
+
+impl<Stream: NetStream> Server<Stream> {
+    /// serve connections accepted on `listener` on a pool of `config.threads`
+    ///
+    /// returns once shutdown was requested and every connection is closed
+    pub fn serve<L: Listener<Stream = Stream>>(self, listener: L) -> io::Result<()> {
+        let handle = self.shutdown_handle();
+        handle.set_addr(listener.local_addr()?);
+
+        let threads = ThreadPool::build(self.config.threads);
+
+        while !handle.is_stopped() {
+            let mut stream = match listener.accept() {
+                Ok(stream) => stream,
+                Err(err) => {
+                    accept_failed(&err);
+                    continue;
+                }
+            };
+            if handle.is_stopped() {
+                break;
+            }
+            // no thread left : answer now rather than queue the connection
+            let stats = threads.stats();
+            if stats.active + stats.queued >= self.config.threads {
+                println!("ERR>> every thread is busy, rejecting {}", stream.peer());
+                let _ = Response::error(StatusCode::SERVICE_UNAVAILABLE).write_to(&mut stream);
+                continue;
+            }
+            let server = self.clone();
+            threads.excute(move || server.manage_connection(stream));
+        }
+
+        println!("Shutting down websocket server");
+        self.close_all();
+        // wait for running connections
+        drop(threads);
+        Ok(())
+    }
+
+    /// send `1001 going away` and close every socket
+    fn close_all(&self) {
+        for (_, writer) in self.writers() {
+            let mut writer = writer.lock().unwrap();
+            let _ = writer.send_close(CLOSE_GOING_AWAY, "server shutdown");
+            // wakes up the thread reading this socket
+            let _ = writer.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// serve a single connection until it is closed
+    pub fn manage_connection(&self, stream: Stream) {
+        if let Err(err) = self.serve_connection(stream) {
+            println!("ERR>> {err}");
+        }
+    }
+
+    fn serve_connection(&self, stream: Stream) -> io::Result<()> {
+        let max_size = Some(self.config.max_payload_size);
+        let mut wc = WebsocketConnection::new(stream.try_clone()?, max_size);
+
+        let reader = HeadReader::default();
+        stream.set_read_timeout(reader.timeout)?;
+        let (req, rest) = reader.read_request(&mut wc.stream)?;
+        stream.set_read_timeout(None)?;
+        wc.feed(&rest);
+
+        if self.shutdown.is_stopped() {
+            return Response::error(StatusCode::SERVICE_UNAVAILABLE).write_to(&mut wc.stream);
+        }
+        wc.accept(&req)?;
+
+        // pings, close answers and messages of the server share this writer
+        let writer = Arc::new(Mutex::new(WebsocketConnection::new(stream, max_size)));
+        let id = self.register(writer.clone());
+        if let Some(callback) = &self.on_connect {
+            callback(self, id);
+        }
+
+        let close = loop {
+            match wc.receive_message() {
+                Ok(Message::Ping(payload)) => {
+                    let _ = writer.lock().unwrap().send_message(Message::Pong(payload));
+                }
+                Ok(Message::Pong(_)) => {}
+                Ok(Message::Close(close)) => {
+                    let mut writer = writer.lock().unwrap();
+                    if !writer.is_closing() {
+                        let _ = writer.send_message(Message::Close(close_answer(&close)));
+                    }
+                    break close;
+                }
+                Ok(message) => {
+                    if let Some(callback) = &self.on_message {
+                        callback(self, id, message);
+                    }
+                }
+                Err(err) => {
+                    if let Some(close) = violation(&err) {
+                        let close = Message::Close(Some(close.clone()));
+                        let _ = writer.lock().unwrap().send_message(close);
+                    }
+                    break None;
+                }
+            }
+        };
+
+        self.connections.lock().unwrap().remove(&id);
+        let _ = writer.lock().unwrap().stream.shutdown(Shutdown::Both);
+        if let Some(callback) = &self.on_close {
+            callback(self, id, close);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{thread, time::Duration};
+
+    use super::*;
+    use crate::{
+        net::pipe::{duplex, Pipe},
+        websockets::{client::Client, message::CLOSE_NORMAL},
+    };
+
+    fn text(text: &str) -> Message {
+        Message::Text(String::from(text))
+    }
+
+    #[test]
+    fn registry_broadcast_and_callbacks() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let mut server = Server::<Pipe>::new(Config {
+            max_payload_size: 1024,
+            ..Config::default()
+        });
+        let log = events.clone();
+        server.on_connect(move |_, id| log.lock().unwrap().push(format!("connect {id}")));
+        server.on_message(|server, id, message| match message {
+            Message::Text(msg) if msg == "who" => {
+                server.send_to(id, text(&format!("you are {id}"))).unwrap()
+            }
+            message => assert_eq!(server.broadcast(message), 2),
+        });
+        let log = events.clone();
+        server.on_close(move |_, id, close| {
+            let code = close.map(|close| close.code);
+            log.lock().unwrap().push(format!("close {id} {code:?}"));
+        });
+
+        // connected one after the other, so that ids are known
+        let connect = |expected: &str| {
+            let (end, client) = duplex();
+            let srv = server.clone();
+            let handle = thread::spawn(move || srv.manage_connection(end));
+            let mut client = Client::new(client, None).connect().unwrap();
+            client.send_message(text("who")).unwrap();
+            assert_eq!(client.read_message().unwrap(), text(expected));
+            (client, handle)
+        };
+        let (mut first, first_handle) = connect("you are 1");
+        let (mut second, second_handle) = connect("you are 2");
+        assert_eq!(server.connections(), vec![1, 2]);
+
+        first.send_message(text("hello")).unwrap();
+        assert_eq!(first.read_message().unwrap(), text("hello"));
+        assert_eq!(second.read_message().unwrap(), text("hello"));
+
+        assert_eq!(
+            server.send_to(3, text("x")).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+
+        // pings are answered by the server
+        second.send_message(Message::Ping(b"p".to_vec())).unwrap();
+        assert_eq!(second.read_message().unwrap(), Message::Pong(b"p".to_vec()));
+
+        second.send_close(CLOSE_NORMAL, "bye").unwrap();
+        assert_eq!(
+            second.read_message().unwrap(),
+            Message::Close(Some(CloseFrame::new(CLOSE_NORMAL, "")))
+        );
+        second_handle.join().unwrap();
+        assert_eq!(server.connections(), vec![1]);
+
+        drop(first);
+        first_handle.join().unwrap();
+        assert!(server.connections().is_empty());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "connect 1",
+                "connect 2",
+                "close 2 Some(1000)",
+                "close 1 None"
+            ]
+        );
+    }
+
+    #[test]
+    fn serve_and_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(Config {
+            threads: 2,
+            ..Config::default()
+        });
+        let handle = server.shutdown_handle();
+        let pusher = server.clone();
+        let serving = thread::spawn(move || server.serve(listener));
+
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut client = Client::new(stream, None)
+            .host(&addr.to_string())
+            .connect()
+            .unwrap();
+        while pusher.connections().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(pusher.broadcast(text("push")), 1);
+        assert_eq!(client.read_message().unwrap(), text("push"));
+
+        handle.shutdown();
+        assert_eq!(
+            client.read_message().unwrap(),
+            Message::Close(Some(CloseFrame::new(CLOSE_GOING_AWAY, "server shutdown")))
+        );
+        serving.join().unwrap().unwrap();
+        assert!(pusher.connections().is_empty());
+    }
+
+    #[test]
+    fn reject_connections_when_every_thread_is_busy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = Server::new(Config {
+            threads: 1,
+            ..Config::default()
+        });
+        let handle = server.shutdown_handle();
+        let watcher = server.clone();
+        let serving = thread::spawn(move || server.serve(listener));
+
+        // holds the only thread
+        let stream = TcpStream::connect(addr).unwrap();
+        let _client = Client::new(stream, None)
+            .host(&addr.to_string())
+            .connect()
+            .unwrap();
+        while watcher.connections().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut res = String::new();
+        TcpStream::connect(addr)
+            .unwrap()
+            .read_to_string(&mut res)
+            .unwrap();
+        assert!(res.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        handle.shutdown();
+        serving.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+use std::fmt::Display;
+
+use super::frame::{Control, Data, Frame, Opcode};
+
+// close codes (RFC 6455 section 7.4.1)
+pub const CLOSE_NORMAL: u16 = 1000;
+pub const CLOSE_GOING_AWAY: u16 = 1001;
+pub const CLOSE_PROTOCOL_ERROR: u16 = 1002;
+pub const CLOSE_UNSUPPORTED: u16 = 1003;
+pub const CLOSE_INVALID_PAYLOAD: u16 = 1007;
+pub const CLOSE_POLICY: u16 = 1008;
+pub const CLOSE_TOO_BIG: u16 = 1009;
+pub const CLOSE_INTERNAL_ERROR: u16 = 1011;
+
+/// longest payload of a control frame
+pub const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// status code and reason of a close frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    pub fn new(code: u16, reason: &str) -> Self {
+        CloseFrame {
+            code,
+            reason: String::from(reason),
+        }
+    }
+    /// code which may be sent in a close frame
+    /// (1004 to 1006 and 1015 are reserved for local use)
+    pub fn is_valid_code(code: u16) -> bool {
+        matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999)
+    }
+    /// close frame payload : `None` when empty, the close frame to answer
+    /// with as error when invalid
+    pub fn parse(payload: &[u8]) -> Result<Option<Self>, CloseFrame> {
+        match payload {
+            [] => Ok(None),
+            [_] => Err(CloseFrame::new(
+                CLOSE_PROTOCOL_ERROR,
+                "truncated close code",
+            )),
+            [high, low, reason @ ..] => {
+                let code = u16::from_be_bytes([*high, *low]);
+                if !Self::is_valid_code(code) {
+                    return Err(CloseFrame::new(CLOSE_PROTOCOL_ERROR, "invalid close code"));
+                }
+                match std::str::from_utf8(reason) {
+                    Ok(reason) => Ok(Some(CloseFrame::new(code, reason))),
+                    Err(_) => Err(CloseFrame::new(
+                        CLOSE_INVALID_PAYLOAD,
+                        "close reason is not utf-8",
+                    )),
+                }
+            }
+        }
+    }
+    pub fn payload(&self) -> Vec<u8> {
+        let mut payload = self.code.to_be_bytes().to_vec();
+        payload.extend_from_slice(self.reason.as_bytes());
+        payload
+    }
+}
+
+impl Display for CloseFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "close {} {}", self.code, self.reason)
+    }
+}
+
+impl std::error::Error for CloseFrame {}
+
+/// complete message, or control frame
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+impl Message {
+    /// frames (a single one) carrying the message
+    pub fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(text) => Frame::create_msg_frame(text),
+            Message::Binary(data) => Frame::create_binary_frame(data),
+            Message::Ping(data) => Frame::create_control_frame(Control::Ping, data),
+            Message::Pong(data) => Frame::create_control_frame(Control::Pong, data),
+            Message::Close(close) => Frame::create_control_frame(
+                Control::Close,
+                close.map(|close| close.payload()).unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+impl Display for Message {
+    /// kind and size, payloads may be large
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Message::Text(text) => write!(f, "text ({} bytes)", text.len()),
+            Message::Binary(data) => write!(f, "binary ({} bytes)", data.len()),
+            Message::Ping(data) => write!(f, "ping ({} bytes)", data.len()),
+            Message::Pong(data) => write!(f, "pong ({} bytes)", data.len()),
+            Message::Close(Some(close)) => write!(f, "{close}"),
+            Message::Close(None) => write!(f, "close"),
+        }
+    }
+}
+
+/// side of the connection : clients mask their frames, servers do not
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Server,
+    Client,
+}
+
+/// validates received frames and reassembles fragmented messages
+/// (RFC 6455 sections 5.4 to 5.6)
+#[derive(Debug)]
+pub struct MessageReader {
+    role: Role,
+    /// longest message, fragments included
+    max_size: usize,
+    /// kind and payload of the fragmented message being received
+    fragments: Option<(Data, Vec<u8>)>,
+}
+
+impl MessageReader {
+    pub fn new(role: Role, max_size: usize) -> Self {
+        MessageReader {
+            role,
+            max_size,
+            fragments: None,
+        }
+    }
+
+    /// handle next frame : `Ok(None)` while a fragmented message is
+    /// incomplete, `Err` with the close frame failing the connection when
+    /// the peer broke the protocol
+    pub fn push(&mut self, frame: Frame) -> Result<Option<Message>, CloseFrame> {
+        let protocol_error = |reason| Err(CloseFrame::new(CLOSE_PROTOCOL_ERROR, reason));
+        let header = &frame.header;
+
+        if header.rsv1 || header.rsv2 || header.rsv3 {
+            return protocol_error("reserved bits set without extension");
+        }
+        match (self.role, header.masked) {
+            (Role::Server, false) => return protocol_error("client frame is not masked"),
+            (Role::Client, true) => return protocol_error("server frame is masked"),
+            _ => {}
+        }
+
+        match header.opcode {
+            Opcode::Reserved => protocol_error("reserved opcode"),
+            Opcode::Control(control) => {
+                if !header.fin {
+                    return protocol_error("fragmented control frame");
+                }
+                if frame.payload.len() > MAX_CONTROL_PAYLOAD {
+                    return protocol_error("control frame payload too long");
+                }
+                let message = match control {
+                    Control::Ping => Message::Ping(frame.payload),
+                    Control::Pong => Message::Pong(frame.payload),
+                    Control::Close => Message::Close(CloseFrame::parse(&frame.payload)?),
+                };
+                Ok(Some(message))
+            }
+            Opcode::Data(Data::Continue) => {
+                let Some((kind, mut data)) = self.fragments.take() else {
+                    return protocol_error("continuation without message");
+                };
+                if data.len() + frame.payload.len() > self.max_size {
+                    return Err(CloseFrame::new(CLOSE_TOO_BIG, "message too large"));
+                }
+                data.extend_from_slice(&frame.payload);
+                if !header.fin {
+                    self.fragments = Some((kind, data));
+                    return Ok(None);
+                }
+                Self::complete(kind, data).map(Some)
+            }
+            Opcode::Data(kind) => {
+                if self.fragments.is_some() {
+                    return protocol_error("new message before end of fragmented message");
+                }
+                if frame.payload.len() > self.max_size {
+                    return Err(CloseFrame::new(CLOSE_TOO_BIG, "message too large"));
+                }
+                if !header.fin {
+                    self.fragments = Some((kind, frame.payload));
+                    return Ok(None);
+                }
+                Self::complete(kind, frame.payload).map(Some)
+            }
+        }
+    }
+
+    fn complete(kind: Data, data: Vec<u8>) -> Result<Message, CloseFrame> {
+        match kind {
+            Data::Text => String::from_utf8(data)
+                .map(Message::Text)
+                .map_err(|_| CloseFrame::new(CLOSE_INVALID_PAYLOAD, "text is not utf-8")),
+            _ => Ok(Message::Binary(data)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn frame(fin: bool, opcode: Opcode, payload: &[u8]) -> Frame {
+        let mut frame = Frame::create_binary_frame(payload.to_vec());
+        frame.header.fin = fin;
+        frame.header.opcode = opcode;
+        frame.header.set_random_mask();
+        frame
+    }
+
+    #[test]
+    fn reassemble_and_validate() {
+        let mut reader = MessageReader::new(Role::Server, 8);
+        let text = Opcode::Data(Data::Text);
+        let cont = Opcode::Data(Data::Continue);
+        let ping = Opcode::Control(Control::Ping);
+
+        // "é" split between fragments, ping in between
+        assert_eq!(reader.push(frame(false, text, b"a\xc3")), Ok(None));
+        assert_eq!(
+            reader.push(frame(true, ping, b"p")),
+            Ok(Some(Message::Ping(b"p".to_vec())))
+        );
+        assert_eq!(
+            reader.push(frame(true, cont, b"\xa9")),
+            Ok(Some(Message::Text(String::from("aé"))))
+        );
+
+        let code = |result: Result<Option<Message>, CloseFrame>| result.unwrap_err().code;
+        assert_eq!(code(reader.push(frame(true, cont, b""))), 1002);
+        assert_eq!(code(reader.push(frame(true, text, b"\xff"))), 1007);
+        assert_eq!(code(reader.push(frame(true, text, b"123456789"))), 1009);
+        assert_eq!(code(reader.push(frame(false, ping, b""))), 1002);
+        assert_eq!(code(reader.push(frame(true, Opcode::Reserved, b""))), 1002);
+
+        let mut unmasked = Frame::create_msg_frame(String::from("x"));
+        assert_eq!(code(reader.push(unmasked)), 1002);
+        unmasked = Frame::create_msg_frame(String::from("x"));
+        assert_eq!(
+            MessageReader::new(Role::Client, 8).push(unmasked),
+            Ok(Some(Message::Text(String::from("x"))))
+        );
+
+        let close = Opcode::Control(Control::Close);
+        assert_eq!(
+            reader.push(frame(true, close, b"\x03\xe8bye")),
+            Ok(Some(Message::Close(Some(CloseFrame::new(1000, "bye")))))
+        );
+        assert_eq!(code(reader.push(frame(true, close, b"\x03"))), 1002);
+        assert_eq!(code(reader.push(frame(true, close, b"\x03\xed"))), 1002);
+    }
+}
@@ -1,4 +1,10 @@
 pub mod client;
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
 pub mod frame;
+pub mod hub;
+pub mod message;
 pub mod server;
 pub mod util;
+
+pub use hub::Server;
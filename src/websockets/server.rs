@@ -1,12 +1,16 @@
 use std::{
-    io::{BufRead, BufReader, Cursor, Read, Write},
+    io::{self, Cursor, Read, Write},
     println,
 };
 
 use crate::{
-    http::header::{RequestHeader, ResponseHeader},
-    utils::{base64::Base64, sha1::Sha1},
-    websockets::frame::Frame,
+    http::{header::RequestHeader, reader::HeadReader, response::Response, status::StatusCode},
+    utils::base64::Base64,
+    websockets::{
+        frame::{Frame, FrameHeader},
+        message::{CloseFrame, Message, MessageReader, Role, CLOSE_PROTOCOL_ERROR, CLOSE_TOO_BIG},
+        util::derive_accept_key,
+    },
 };
 
 #[derive(Debug)]
@@ -61,6 +65,14 @@ pub struct WebsocketConnection<Stream> {
     pub max_payload_size: usize,
 
     pub connection: Connection,
+    /// bytes received but not decoded yet
+    buffer: Vec<u8>,
+    /// clients mask the frames they send, servers expect masked frames
+    role: Role,
+    /// reassembles messages for `read_message`
+    reader: MessageReader,
+    /// close frame sent, waiting for the answer of the peer
+    closing: bool,
 }
 
 impl<Stream> WebsocketConnection<Stream> {
@@ -68,19 +80,32 @@ impl<Stream> WebsocketConnection<Stream> {
     /// `stream` : Abstraction represents data stream
     /// `max_size` : max size of payload (default : 16 MB)
     pub fn new(stream: Stream, max_size: Option<usize>) -> Self {
-        match max_size {
-            Some(size) => Self {
-                stream,
-                max_payload_size: size,
-                connection: Connection::new(),
-            },
-            None => Self {
-                stream,
-                max_payload_size: 16 * 1024 * 1024,
-                connection: Connection::new(),
-            },
+        Self::with_role(stream, max_size, Role::Server)
+    }
+    /// client side of a connection whose handshake is done (see `Client::connect`)
+    pub fn client(stream: Stream, max_size: Option<usize>) -> Self {
+        Self::with_role(stream, max_size, Role::Client)
+    }
+    fn with_role(stream: Stream, max_size: Option<usize>, role: Role) -> Self {
+        let max_payload_size = max_size.unwrap_or(16 * 1024 * 1024);
+        Self {
+            stream,
+            max_payload_size,
+            connection: Connection::new(),
+            buffer: Vec::new(),
+            role,
+            reader: MessageReader::new(role, max_payload_size),
+            closing: false,
         }
     }
+    pub fn role(&self) -> Role {
+        self.role
+    }
+    /// bytes already read from the stream (e.g. received after the
+    /// handshake head), decoded before reading the stream again
+    pub fn feed(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
 }
 
 impl<Stream> WebsocketConnection<Stream>
@@ -91,52 +116,88 @@ where
     pub fn handshake(&mut self) -> std::io::Result<()> {
         println!("handshaking ...");
 
-        self.connection.handshake();
-
-        let mut reader = BufReader::new(&mut self.stream);
+        let (req_hdr, rest) = HeadReader::default().read_request(&mut self.stream)?;
+        // frames sent right after the handshake
+        self.feed(&rest);
 
-        let rsv: Vec<u8> = reader.fill_buf().unwrap().to_vec();
-        reader.consume(rsv.len());
+        println!("Request: {}", req_hdr.format());
 
-        let req = String::from_utf8(rsv).unwrap();
-
-        println!("Request: {}", req);
+        self.accept(&req_hdr)
+    }
 
-        let req_hdr = RequestHeader::from(&req);
+    /// answer websocket upgrade request which was already read from stream
+    ///
+    /// invalid upgrade requests are answered with 400 or 426 and fail
+    pub fn accept(&mut self, req_hdr: &RequestHeader) -> std::io::Result<()> {
+        self.connection.handshake();
 
         println!("Header: {:?}", req_hdr);
 
-        let swk = req_hdr.get("Sec-WebSocket-Key").unwrap();
+        let swk = match Self::check_upgrade(req_hdr) {
+            Ok(swk) => swk,
+            Err(res) => {
+                self.connection.fail();
+                res.write_to(&mut self.stream)?;
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("websocket upgrade rejected ({})", res.status()),
+                ));
+            }
+        };
 
         println!("Sec-WebSocket-Key : {:?}", swk);
 
-        const WS_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
-
-        let hash = Sha1::from([swk.as_bytes(), WS_GUID].concat())
-            .digest()
-            .as_byte();
-
-        let swa = Base64.encode(&hash);
-
-        let mut res = ResponseHeader::default();
+        let swa = derive_accept_key(swk.as_bytes());
 
-        res.set("Sec-WebSocket-Accept", &swa);
-        res.set("Upgrade", "websocket");
-        res.set("Connection", "Upgrade");
+        let res = Response::new(StatusCode::SWITCHING_PROTOCOLS)
+            .header("Sec-WebSocket-Accept", &swa)
+            .header("Upgrade", "websocket")
+            .header("Connection", "Upgrade");
 
         println!("Response created: {:?}", res);
 
-        self.stream.write_all(res.format().as_bytes()).unwrap();
-
-        println!("Responsed with");
-        println!("{}", res.format());
+        res.write_to(&mut self.stream)?;
 
         self.connection.connect();
 
         Ok(())
     }
 
+    /// `Sec-WebSocket-Key` of a valid upgrade request, or the error response
+    fn check_upgrade(req_hdr: &RequestHeader) -> Result<&str, Response> {
+        let has_token = |key: &str, token: &str| {
+            req_hdr.get_all(key).iter().any(|val| {
+                val.split(',')
+                    .any(|item| item.trim().eq_ignore_ascii_case(token))
+            })
+        };
+
+        if req_hdr.method() != "GET" {
+            return Err(Response::error(StatusCode::BAD_REQUEST));
+        }
+        if !has_token("Upgrade", "websocket") || !has_token("Connection", "Upgrade") {
+            return Err(Response::error(StatusCode::UPGRADE_REQUIRED)
+                .header("Upgrade", "websocket")
+                .header("Connection", "Upgrade, close"));
+        }
+        if req_hdr.get("Sec-WebSocket-Version").map(|v| v.trim()) != Some("13") {
+            return Err(
+                Response::error(StatusCode::UPGRADE_REQUIRED).header("Sec-WebSocket-Version", "13")
+            );
+        }
+        // base64 of a 16 bytes nonce
+        match req_hdr.get("Sec-WebSocket-Key") {
+            Some(swk) if Base64.decode(swk).is_ok_and(|nonce| nonce.len() == 16) => Ok(swk),
+            _ => Err(Response::error(StatusCode::BAD_REQUEST)),
+        }
+    }
+
     pub fn receive(&mut self) -> Frame {
+        self.try_receive().unwrap()
+    }
+
+    /// receive next frame, failing once the peer closed the stream
+    pub fn try_receive(&mut self) -> std::io::Result<Frame> {
         // match self.connection.state {
         //     ConnectionState::NeedHandShake => self.handshake(),
         //     ConnectionState::MidHandShake => {}
@@ -145,27 +206,224 @@ where
         //     _ => {}
         // }
 
-        let mut reader = BufReader::new(&mut self.stream);
+        let mut chunk = [0u8; 4096];
+        loop {
+            if let Some(frame) = self.decode()? {
+                println!("{}", frame);
+                return Ok(frame);
+            }
+            let len = match self.stream.read(&mut chunk) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+            if len == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    "connection closed",
+                ));
+            }
+            self.buffer.extend_from_slice(&chunk[..len]);
+        }
+    }
 
-        let mut rsv: Vec<u8> = reader.fill_buf().unwrap().to_vec();
-        reader.consume(rsv.len());
+    /// take the first frame out of the buffer, `None` while it is incomplete
+    fn decode(&mut self) -> io::Result<Option<Frame>> {
+        let mut cursor = Cursor::new(&self.buffer);
+        let header = match FrameHeader::parse(&mut cursor) {
+            Ok(Some(header)) => header,
+            Ok(None) => return Ok(None),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    CloseFrame::new(CLOSE_PROTOCOL_ERROR, &err.to_string()),
+                ))
+            }
+        };
+        if header.payloadlength > self.max_payload_size as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                CloseFrame::new(CLOSE_TOO_BIG, "frame payload too large"),
+            ));
+        }
 
-        let frame = Frame::parse(&mut rsv);
+        let end = (cursor.position() as usize).saturating_add(header.payloadlength as usize);
+        if self.buffer.len() < end {
+            return Ok(None);
+        }
+        let raw: Vec<u8> = self.buffer.drain(..end).collect();
+        Frame::parse(&raw).map(Some)
+    }
+
+    /// next message : fragments are reassembled, pings answered and a
+    /// close frame of the peer answered before being returned
+    ///
+    /// when the peer breaks the protocol, the connection is failed with
+    /// the matching close code and an `InvalidData` error wrapping the
+    /// sent `CloseFrame` is returned
+    pub fn read_message(&mut self) -> io::Result<Message> {
+        match self.receive_message() {
+            Ok(Message::Ping(payload)) => {
+                self.send_message(Message::Pong(payload.clone()))?;
+                Ok(Message::Ping(payload))
+            }
+            Ok(Message::Close(close)) => {
+                if !self.closing {
+                    // the peer may already be gone, the close is returned anyway
+                    let _ = self.send_message(Message::Close(close_answer(&close)));
+                }
+                Ok(Message::Close(close))
+            }
+            Err(err) => {
+                if let Some(close) = violation(&err) {
+                    let _ = self.send_message(Message::Close(Some(close.clone())));
+                }
+                Err(err)
+            }
+            message => message,
+        }
+    }
+
+    /// next message, like `read_message` but nothing is written : pings,
+    /// close frames and violations (`InvalidData` wrapping the `CloseFrame`
+    /// to send) are left to the caller, e.g. when another handle writes
+    pub fn receive_message(&mut self) -> io::Result<Message> {
+        loop {
+            let frame = match self.try_receive() {
+                Ok(frame) => frame,
+                Err(err) => {
+                    if let Some(close) = violation(&err) {
+                        println!("ERR>> failing websocket connection : {}", close);
+                        self.connection.fail();
+                    }
+                    return Err(err);
+                }
+            };
+            match self.reader.push(frame) {
+                Ok(None) => continue,
+                Ok(Some(message)) => {
+                    if let Message::Close(_) = message {
+                        self.connection.close();
+                    }
+                    return Ok(message);
+                }
+                Err(close) => {
+                    println!("ERR>> failing websocket connection : {}", close);
+                    self.connection.fail();
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, close));
+                }
+            }
+        }
+    }
 
-        println!("{}", frame);
+    /// a close frame was sent and the answer of the peer is awaited
+    pub fn is_closing(&self) -> bool {
+        self.closing
+    }
 
-        frame
+    /// write a frame in a single write, masked on the client side
+    fn write_frame(&mut self, mut frame: Frame) -> io::Result<()> {
+        if self.role == Role::Client {
+            frame.header.set_random_mask();
+        }
+        let mut raw = Vec::with_capacity(frame.payload.len() + 14);
+        frame.format(&mut raw)?;
+        self.stream.write_all(&raw)
+    }
+
+    pub fn send_message(&mut self, message: Message) -> io::Result<()> {
+        self.write_frame(message.into_frame())
+    }
+    /// start the closing handshake, the answer of the peer is returned
+    /// by `read_message`
+    pub fn send_close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        self.closing = true;
+        self.send_message(Message::Close(Some(CloseFrame::new(code, reason))))
     }
 
     /// send msg to client
-    pub fn send_msg(&mut self, msg: String) {
-        let frame = Frame::create_msg_frame(msg);
-        frame.format(&mut self.stream).unwrap();
+    pub fn send_msg(&mut self, msg: String) -> io::Result<()> {
+        self.send_message(Message::Text(msg))
+    }
+    /// send binary data to client
+    pub fn send_binary(&mut self, data: Vec<u8>) -> io::Result<()> {
+        self.send_message(Message::Binary(data))
     }
-    pub fn send_pong(&mut self) {
-        let frame = Frame::create_pong_frame();
-        frame.format(&mut self.stream).unwrap();
+    pub fn send_pong(&mut self) -> io::Result<()> {
+        self.send_message(Message::Pong(Vec::new()))
     }
     /// close connection
     pub fn close(&self) {}
 }
+
+/// close frame failing the connection, when `err` is a protocol violation
+pub fn violation(err: &io::Error) -> Option<&CloseFrame> {
+    err.get_ref()?.downcast_ref::<CloseFrame>()
+}
+
+/// answer to the close frame of the peer : same code, without reason
+pub fn close_answer(close: &Option<CloseFrame>) -> Option<CloseFrame> {
+    close.as_ref().map(|close| CloseFrame::new(close.code, ""))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::websockets::frame::{Data, Opcode};
+
+    #[test]
+    fn keep_frames_sent_with_handshake() {
+        let mut raw = Vec::from(
+            &b"GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\r\n"[..],
+        );
+        // two frames, masked with zero key, right after the head
+        raw.extend_from_slice(b"\x81\x82\0\0\0\0hi\x82\x81\0\0\0\0\x07");
+
+        let mut wc = WebsocketConnection::new(Cursor::new(raw), None);
+        wc.handshake().unwrap();
+
+        let frame = wc.try_receive().unwrap();
+        assert_eq!(
+            (frame.header.opcode, frame.payload),
+            (Opcode::Data(Data::Text), b"hi".to_vec())
+        );
+        let frame = wc.try_receive().unwrap();
+        assert_eq!(
+            (frame.header.opcode, frame.payload),
+            (Opcode::Data(Data::Binary), vec![7])
+        );
+        assert_eq!(
+            wc.try_receive().unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn reject_invalid_upgrade() {
+        let answer = |head: &str| {
+            let mut wc = WebsocketConnection::new(Cursor::new(Vec::new()), None);
+            assert!(wc.accept(&RequestHeader::from(head)).is_err());
+            String::from_utf8(wc.stream.into_inner()).unwrap()
+        };
+
+        let res = answer("GET / HTTP/1.1\r\nHost: x\r\n\r\n");
+        assert!(res.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+
+        let res = answer(
+            "GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: keep-alive, Upgrade\r\nSec-WebSocket-Version: 8\r\n\r\n",
+        );
+        assert!(res.starts_with("HTTP/1.1 426 Upgrade Required\r\n"));
+        assert!(res.contains("Sec-WebSocket-Version: 13\r\n"));
+
+        let res = answer(
+            "GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        );
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        let res = answer(
+            "GET / HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Version: 13\r\nSec-WebSocket-Key: c2hvcnQ=\r\n\r\n",
+        );
+        assert!(res.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+    }
+}
@@ -1,3 +1,35 @@
-pub fn derive_accept_key(req_key: &[u8]) {
-    const WS_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+use crate::utils::{base64::Base64, sha1::Sha1};
+
+/// GUID appended to the client key (RFC 6455 section 1.3)
+const WS_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// `Sec-WebSocket-Accept` answering the client `Sec-WebSocket-Key`
+pub fn derive_accept_key(req_key: &[u8]) -> String {
+    let mut sha = Sha1::new();
+    sha.update(req_key);
+    sha.update(WS_GUID);
+    Base64.encode(&sha.finalize().as_byte())
+}
+
+/// random `Sec-WebSocket-Key` (base64 of a 16 bytes nonce)
+pub fn generate_key() -> String {
+    let nonce: [u8; 16] = rand::random();
+    Base64.encode(&nonce)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn accept_key() {
+        assert_eq!(
+            derive_accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+
+        let key = generate_key();
+        assert_eq!(Base64.decode(&key).unwrap().len(), 16);
+        assert_ne!(key, generate_key());
+    }
 }
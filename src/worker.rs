@@ -1,14 +1,320 @@
 use std::{
-    println,
-    sync::{mpsc, Arc, Mutex},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt::Display,
+    marker::PhantomData,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
+/// sequence number of a queued job
+pub type JobId = u64;
+
+/// default number of jobs waiting for a worker
+pub const QUEUE_SIZE: usize = 1024;
+/// default time an extra worker waits for a job before exiting
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// thread name prefix (threads are named `<name>-<id>`)
+    pub name: String,
+    /// workers kept alive when idle
+    pub min_size: usize,
+    /// workers running at most (spawned while jobs wait for a worker)
+    pub max_size: usize,
+    /// time a worker above `min_size` waits for a job before exiting
+    pub idle_timeout: Duration,
+    /// jobs waiting for a worker before `excute` blocks
+    pub queue_size: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            name: String::from("worker"),
+            min_size: 1,
+            max_size: 4,
+            idle_timeout: IDLE_TIMEOUT,
+            queue_size: QUEUE_SIZE,
+        }
+    }
+}
+
+/// snapshot of the pool counters
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Stats {
+    /// running worker threads
+    pub workers: usize,
+    /// jobs waiting for a worker
+    pub queued: usize,
+    /// jobs running
+    pub active: usize,
+    /// jobs which returned
+    pub completed: usize,
+    /// jobs which panicked
+    pub panicked: usize,
+}
+
+/// outcome of `ThreadPool::shutdown`
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShutdownReport {
+    /// queued jobs dropped without running
+    pub abandoned: Vec<JobId>,
+    /// jobs still running when the timeout expired (left detached)
+    pub unfinished: Vec<JobId>,
+}
+
+impl ShutdownReport {
+    /// every job ran to the end
+    pub fn is_complete(&self) -> bool {
+        self.abandoned.is_empty() && self.unfinished.is_empty()
+    }
+}
+
+/// why a submitted job produced no result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoinError {
+    /// job panicked
+    Panicked,
+    /// job was dropped without running (pool shut down)
+    Cancelled,
+}
+
+impl Display for JoinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JoinError::Panicked => write!(f, "job panicked"),
+            JoinError::Cancelled => write!(f, "job was cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+/// result of a job queued with `submit`
+pub struct JobHandle<T> {
+    id: JobId,
+    result: mpsc::Receiver<Result<T, JoinError>>,
+}
+
+impl<T> JobHandle<T> {
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+    /// wait for the job and take its result
+    pub fn join(self) -> Result<T, JoinError> {
+        self.result.recv().unwrap_or(Err(JoinError::Cancelled))
+    }
+    /// take the result if the job is done (`None` while it is queued or running)
+    ///
+    /// the result is returned once, `Cancelled` is returned afterwards
+    pub fn try_join(&mut self) -> Option<Result<T, JoinError>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(mpsc::TryRecvError::Empty) => None,
+            Err(mpsc::TryRecvError::Disconnected) => Some(Err(JoinError::Cancelled)),
+        }
+    }
+}
+
+/// wrap `f` to send its result through the returned handle
+///
+/// a panic is reported to the handle, then resumed for the pool to count it
+fn with_handle<'a, F, T>(
+    f: F,
+) -> (
+    impl FnOnce() + Send + 'a,
+    mpsc::Receiver<Result<T, JoinError>>,
+)
+where
+    F: FnOnce() -> T + Send + 'a,
+    T: Send + 'a,
+{
+    let (tx, rx) = mpsc::channel();
+    let job = move || match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => {
+            let _ = tx.send(Ok(value));
+        }
+        Err(payload) => {
+            let _ = tx.send(Err(JoinError::Panicked));
+            panic::resume_unwind(payload)
+        }
+    };
+    (job, rx)
+}
+
+/// jobs of a scope still queued or running
+#[derive(Default)]
+struct Pending {
+    count: Mutex<usize>,
+    done: Condvar,
+}
+
+/// counts a scoped job, released when the job ran or was dropped
+struct PendingJob(Arc<Pending>);
+
+impl PendingJob {
+    fn new(pending: &Arc<Pending>) -> Self {
+        *pending.count.lock().unwrap() += 1;
+        PendingJob(Arc::clone(pending))
+    }
+}
+
+impl Drop for PendingJob {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock().unwrap();
+        *count -= 1;
+        if *count == 0 {
+            self.0.done.notify_all();
+        }
+    }
+}
+
+/// queues jobs borrowing data which outlives `ThreadPool::scope`
+pub struct Scope<'pool, 'scope> {
+    pool: &'pool ThreadPool,
+    pending: Arc<Pending>,
+    /// invariant : jobs can not borrow data living shorter than the scope
+    _marker: PhantomData<&'scope mut &'scope ()>,
+}
+
+impl<'scope> Scope<'_, 'scope> {
+    /// queue job, waiting while the queue is full
+    pub fn execute<F>(&self, f: F) -> JobId
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        let pending = PendingJob::new(&self.pending);
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            let _pending = pending;
+            f()
+        });
+        // SAFETY: `ThreadPool::scope` does not return before every job
+        // queued here ran or was dropped, so borrowed data outlives the job
+        let job: Job =
+            unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool.excute(job)
+    }
+    /// queue job and get a handle on its result
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'scope,
+        T: Send + 'scope,
+    {
+        let (job, result) = with_handle(f);
+        let id = self.execute(job);
+        JobHandle { id, result }
+    }
+
+    fn wait(&self) {
+        let mut count = self.pending.count.lock().unwrap();
+        while *count > 0 {
+            count = self.pending.done.wait(count).unwrap();
+        }
+    }
+}
+
+struct State {
+    jobs: VecDeque<(JobId, Job)>,
+    /// ids of running jobs
+    running: HashSet<JobId>,
+    closed: bool,
+    next_job: JobId,
+    next_worker: usize,
+    /// worker threads alive
+    alive: usize,
+    /// workers waiting for a job
+    idle: usize,
+}
+
+/// state shared by the pool and its workers
+struct Shared {
+    config: Config,
+    state: Mutex<State>,
+    /// signaled when a job is queued or the pool is closed
+    available: Condvar,
+    /// signaled when a job is taken from the queue
+    space: Condvar,
+    /// signaled when a worker exits
+    exited: Condvar,
+
+    completed: AtomicUsize,
+    panicked: AtomicUsize,
+
+    /// worker id -> thread (respawned workers replace their entry)
+    workers: Mutex<HashMap<usize, Worker>>,
+}
+
+impl Shared {
+    /// start worker `id` (new id when `None`), `state` must be locked by the caller
+    fn spawn(self: &Arc<Self>, state: &mut State, id: Option<usize>) {
+        let id = id.unwrap_or_else(|| {
+            state.next_worker += 1;
+            state.next_worker - 1
+        });
+        state.alive += 1;
+        let worker = Worker::build(id, Arc::clone(self));
+        self.workers.lock().unwrap().insert(id, worker);
+    }
+
+    /// queue job and grow the pool when no worker is waiting for it
+    fn push(self: &Arc<Self>, mut state: MutexGuard<State>, job: Job) -> JobId {
+        let id = state.next_job;
+        state.next_job += 1;
+        state.jobs.push_back((id, job));
+        if state.idle < state.jobs.len() && state.alive < self.config.max_size {
+            self.spawn(&mut state, None);
+        }
+        self.available.notify_one();
+        id
+    }
+
+    /// wait for next job
+    ///
+    /// `None` once the pool is closed and drained,
+    /// or when this worker is above `min_size` and idle for `idle_timeout`
+    ///
+    /// the worker is no longer counted as alive once `None` is returned :
+    /// idle workers timing out together see each other leave and never
+    /// go below `min_size`
+    fn next_job(&self) -> Option<(JobId, Job)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some((id, job)) = state.jobs.pop_front() {
+                state.running.insert(id);
+                self.space.notify_one();
+                return Some((id, job));
+            }
+            if state.closed {
+                state.alive -= 1;
+                return None;
+            }
+            state.idle += 1;
+            let (guard, wait) = self
+                .available
+                .wait_timeout(state, self.config.idle_timeout)
+                .unwrap();
+            state = guard;
+            state.idle -= 1;
+            if wait.timed_out() && state.jobs.is_empty() && state.alive > self.config.min_size {
+                state.alive -= 1;
+                return None;
+            }
+        }
+    }
+}
+
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
+    shared: Arc<Shared>,
+    /// set by `shutdown`, `drop` has nothing left to do
+    finished: bool,
 }
 
 impl ThreadPool {
@@ -20,66 +326,401 @@ impl ThreadPool {
     ///
     /// The `build` function will panic if the size is zero.
     pub fn build(size: usize) -> ThreadPool {
-        assert!(size > 0);
+        Self::with_config(Config {
+            min_size: size,
+            max_size: size,
+            ..Config::default()
+        })
+    }
 
-        let (sender, recv) = mpsc::channel();
-        let recv = Arc::new(Mutex::new(recv));
-        let mut workers = Vec::with_capacity(size);
+    /// Create a new ThreadPool from `config`, starting `min_size` workers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the max size or the queue size is zero,
+    /// or if the min size is above the max size.
+    pub fn with_config(config: Config) -> ThreadPool {
+        assert!(config.max_size > 0);
+        assert!(config.min_size <= config.max_size);
+        assert!(config.queue_size > 0);
 
-        for id in 0..size {
-            workers.push(Worker::build(id, Arc::clone(&recv)));
+        let min_size = config.min_size;
+        let shared = Arc::new(Shared {
+            config,
+            state: Mutex::new(State {
+                jobs: VecDeque::new(),
+                running: HashSet::new(),
+                closed: false,
+                next_job: 0,
+                next_worker: 0,
+                alive: 0,
+                idle: 0,
+            }),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            exited: Condvar::new(),
+            completed: AtomicUsize::new(0),
+            panicked: AtomicUsize::new(0),
+            workers: Mutex::new(HashMap::new()),
+        });
+
+        let mut state = shared.state.lock().unwrap();
+        for _ in 0..min_size {
+            shared.spawn(&mut state, None);
         }
+        drop(state);
 
         ThreadPool {
-            workers,
-            sender: Some(sender),
+            shared,
+            finished: false,
         }
     }
 
-    pub fn excute<F>(&self, f: F)
+    /// queue job, waiting while the queue is full
+    pub fn excute<F>(&self, f: F) -> JobId
     where
         F: FnOnce() + Send + 'static,
     {
-        let job = Box::new(f);
+        let mut state = self.shared.state.lock().unwrap();
+        while state.jobs.len() >= self.shared.config.queue_size {
+            state = self.shared.space.wait(state).unwrap();
+        }
+        self.shared.push(state, Box::new(f))
+    }
+
+    /// queue job, giving it back when the queue is full
+    pub fn try_execute<F>(&self, f: F) -> Result<JobId, F>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let state = self.shared.state.lock().unwrap();
+        if state.jobs.len() >= self.shared.config.queue_size {
+            return Err(f);
+        }
+        Ok(self.shared.push(state, Box::new(f)))
+    }
+
+    /// queue job and get a handle on its result
+    pub fn submit<F, T>(&self, f: F) -> JobHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (job, result) = with_handle(f);
+        let id = self.excute(job);
+        JobHandle { id, result }
+    }
+
+    /// run `f` with a scope queuing jobs which may borrow from the caller
+    ///
+    /// returns once every job queued through the scope is done.
+    /// scoped jobs must not wait for other jobs of a pool with no idle worker
+    pub fn scope<'pool, 'scope, F, R>(&'pool self, f: F) -> R
+    where
+        F: FnOnce(&Scope<'pool, 'scope>) -> R,
+    {
+        let scope = Scope {
+            pool: self,
+            pending: Arc::new(Pending::default()),
+            _marker: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        scope.wait();
+        match result {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
+    }
+
+    pub fn stats(&self) -> Stats {
+        let state = self.shared.state.lock().unwrap();
+        Stats {
+            workers: state.alive,
+            queued: state.jobs.len(),
+            active: state.running.len(),
+            completed: self.shared.completed.load(Ordering::SeqCst),
+            panicked: self.shared.panicked.load(Ordering::SeqCst),
+        }
+    }
+
+    /// stop accepting jobs and run queued ones for at most `timeout`
+    ///
+    /// jobs still queued after `timeout` are dropped, running ones are left
+    /// to finish on detached threads; both are listed in the report
+    pub fn shutdown(mut self, timeout: Duration) -> ShutdownReport {
+        self.finished = true;
+        self.finish(Some(Instant::now() + timeout))
+    }
+
+    fn finish(&self, deadline: Option<Instant>) -> ShutdownReport {
+        let mut state = self.shared.state.lock().unwrap();
+        state.closed = true;
+        self.shared.available.notify_all();
+
+        while state.alive > 0 {
+            state = match deadline {
+                None => self.shared.exited.wait(state).unwrap(),
+                Some(deadline) => {
+                    let Some(remain) = deadline.checked_duration_since(Instant::now()) else {
+                        break;
+                    };
+                    self.shared.exited.wait_timeout(state, remain).unwrap().0
+                }
+            };
+        }
+
+        let abandoned: Vec<JobId> = state.jobs.drain(..).map(|(id, _)| id).collect();
+        let mut unfinished: Vec<JobId> = state.running.iter().copied().collect();
+        unfinished.sort();
+        // every worker left `next_job` : the rest of `Worker::run` is short
+        let exited = state.alive == 0;
+        drop(state);
 
-        self.sender.as_ref().unwrap().send(job).unwrap();
+        // join exited workers, detach the others
+        let workers: Vec<Worker> = self
+            .shared
+            .workers
+            .lock()
+            .unwrap()
+            .drain()
+            .map(|(_, w)| w)
+            .collect();
+        for worker in workers {
+            if exited || worker.thread.is_finished() {
+                let _ = worker.thread.join();
+            }
+        }
+
+        ShutdownReport {
+            abandoned,
+            unfinished,
+        }
     }
 }
+
 impl Drop for ThreadPool {
+    /// run queued jobs and wait for every worker
     fn drop(&mut self) {
-        drop(self.sender.take());
-
-        for worker in &mut self.workers {
-            println!("Shutting down worker {}", worker.id);
-            if let Some(thread) = worker.thread.take() {
-                thread.join().unwrap()
-            }
+        if !self.finished {
+            self.finish(None);
         }
     }
 }
 
 struct Worker {
-    id: usize,
-    thread: Option<thread::JoinHandle<()>>,
+    thread: thread::JoinHandle<()>,
 }
 impl Worker {
-    fn build(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || loop {
-            let sig = receiver.lock().unwrap().recv();
-            match sig {
-                Ok(job) => {
-                    println!("Worker {id} got a job; excuting.");
-                    job();
-                }
-                Err(_) => {
-                    println!("Worker {id} disconnected; shutting down.");
-                    break;
-                }
+    fn build(id: usize, shared: Arc<Shared>) -> Worker {
+        let thread = thread::Builder::new()
+            .name(format!("{}-{id}", shared.config.name))
+            .spawn(move || Self::run(id, shared))
+            .expect("failed to spawn worker thread");
+        Worker { thread }
+    }
+
+    fn run(id: usize, shared: Arc<Shared>) {
+        let mut respawn = false;
+        while let Some((job_id, job)) = shared.next_job() {
+            let result = panic::catch_unwind(AssertUnwindSafe(job));
+            shared.state.lock().unwrap().running.remove(&job_id);
+
+            if result.is_ok() {
+                shared.completed.fetch_add(1, Ordering::SeqCst);
+                continue;
             }
+            shared.panicked.fetch_add(1, Ordering::SeqCst);
+            // replace this thread : state left by the job (thread locals, ...)
+            // is not shared with the next ones
+            respawn = true;
+            break;
+        }
+
+        let mut state = shared.state.lock().unwrap();
+        if respawn {
+            state.alive -= 1;
+            shared.spawn(&mut state, Some(id));
+        } else if !state.closed {
+            // idle worker above min size : detach its own handle
+            shared.workers.lock().unwrap().remove(&id);
+        }
+        shared.exited.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::mpsc,
+        time::{Duration, Instant},
+    };
+
+    use super::*;
+
+    fn wait_until(pool: &ThreadPool, cond: impl Fn(Stats) -> bool) -> Stats {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let stats = pool.stats();
+            if cond(stats) || Instant::now() > deadline {
+                return stats;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn bounded_queue() {
+        let pool = ThreadPool::with_config(Config {
+            max_size: 1,
+            queue_size: 1,
+            ..Config::default()
+        });
+        let (started_tx, started_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+
+        pool.excute(move || {
+            started_tx.send(()).unwrap();
+            gate_rx.recv().unwrap();
+        });
+        started_rx.recv().unwrap();
+
+        assert!(pool.try_execute(|| {}).is_ok());
+        // rejected job is handed back
+        let ran = Arc::new(AtomicUsize::new(0));
+        let counter = ran.clone();
+        let rejected = pool.try_execute(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        rejected.unwrap_err()();
+        assert_eq!(ran.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            pool.stats(),
+            Stats {
+                workers: 1,
+                queued: 1,
+                active: 1,
+                completed: 0,
+                panicked: 0
+            }
+        );
+
+        gate_tx.send(()).unwrap();
+        let stats = wait_until(&pool, |stats| stats.completed == 2);
+        assert_eq!((stats.queued, stats.active, stats.completed), (0, 0, 2));
+    }
+
+    #[test]
+    fn respawn_worker_after_panic() {
+        let pool = ThreadPool::with_config(Config {
+            name: String::from("test-pool"),
+            max_size: 1,
+            ..Config::default()
         });
-        Worker {
-            id,
-            thread: Some(thread),
+        pool.excute(|| panic!("job failure"));
+
+        let (tx, rx) = mpsc::channel();
+        pool.excute(move || {
+            tx.send(thread::current().name().map(String::from)).unwrap();
+        });
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap().as_deref(),
+            Some("test-pool-0")
+        );
+
+        let stats = wait_until(&pool, |stats| stats.completed == 1);
+        assert_eq!((stats.completed, stats.panicked), (1, 1));
+    }
+
+    #[test]
+    fn grow_under_pressure_and_shrink_when_idle() {
+        let pool = ThreadPool::with_config(Config {
+            min_size: 1,
+            max_size: 3,
+            idle_timeout: Duration::from_millis(20),
+            ..Config::default()
+        });
+        let (started_tx, started_rx) = mpsc::channel();
+        let gate = Arc::new(Mutex::new(()));
+        let closed = gate.lock().unwrap();
+
+        for _ in 0..3 {
+            let (started_tx, gate) = (started_tx.clone(), gate.clone());
+            pool.excute(move || {
+                started_tx.send(()).unwrap();
+                drop(gate.lock().unwrap());
+            });
+        }
+        for _ in 0..3 {
+            started_rx.recv_timeout(Duration::from_secs(5)).unwrap();
         }
+        assert_eq!(pool.stats().workers, 3);
+
+        drop(closed);
+        let stats = wait_until(&pool, |stats| stats.workers == 1 && stats.completed == 3);
+        assert_eq!((stats.workers, stats.completed), (1, 3));
+
+        // idle workers timing out together never go below `min_size`
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(pool.stats().workers, 1);
+    }
+
+    #[test]
+    fn shutdown_reports_jobs_left() {
+        let pool = ThreadPool::build(1);
+        let (started_tx, started_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+
+        let running = pool.excute(move || {
+            started_tx.send(()).unwrap();
+            gate_rx.recv().unwrap();
+        });
+        let queued = [pool.excute(|| {}), pool.excute(|| {})];
+        started_rx.recv().unwrap();
+
+        let report = pool.shutdown(Duration::from_millis(20));
+        assert_eq!(report.unfinished, vec![running]);
+        assert_eq!(report.abandoned, queued);
+        gate_tx.send(()).unwrap();
+
+        let pool = ThreadPool::build(2);
+        for _ in 0..4 {
+            pool.excute(|| thread::sleep(Duration::from_millis(1)));
+        }
+        assert!(pool.shutdown(Duration::from_secs(5)).is_complete());
+    }
+
+    #[test]
+    fn submit_and_join() {
+        let pool = ThreadPool::build(2);
+        assert_eq!(pool.submit(|| 6 * 7).join(), Ok(42));
+        assert_eq!(
+            pool.submit(|| -> u8 { panic!("job failure") }).join(),
+            Err(JoinError::Panicked)
+        );
+
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let mut handle = pool.submit(move || gate_rx.recv().is_ok());
+        assert_eq!(handle.try_join(), None);
+        gate_tx.send(()).unwrap();
+        assert_eq!(handle.join(), Ok(true));
+    }
+
+    #[test]
+    fn scope_borrows_caller_data() {
+        let pool = ThreadPool::build(3);
+        let targets: Vec<i32> = (1..=4).collect();
+        let mut written = vec![0; targets.len()];
+
+        let total = pool.scope(|scope| {
+            for (target, out) in targets.iter().zip(written.iter_mut()) {
+                scope.execute(move || *out = target * 10);
+            }
+            let handles: Vec<JobHandle<i32>> =
+                targets.iter().map(|t| scope.submit(move || *t)).collect();
+            handles.into_iter().map(|h| h.join().unwrap()).sum::<i32>()
+        });
+
+        assert_eq!(total, 10);
+        assert_eq!(written, vec![10, 20, 30, 40]);
     }
 }